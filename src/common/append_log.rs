@@ -0,0 +1,24 @@
+use std::fs::{self, File};
+use std::io;
+
+/// Opens an append only file whit an entry per line, creating it if it does not exist, and
+/// returns it together whit its complete lines. A torn write can only leave the last line
+/// whitout its newline, that part is cut off so the next entry starts on a line of its own
+pub fn open(path: &str) -> io::Result<(File, String)> {
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    let mut content = fs::read(path)?;
+    let complete = content
+        .iter()
+        .rposition(|byte| *byte == b'\n')
+        .map_or(0, |newline| newline + 1);
+    if complete < content.len() {
+        println!("[{}] cutting off a torn entry", path);
+        file.set_len(complete as u64)?;
+        file.sync_all()?;
+        content.truncate(complete);
+    }
+    Ok((file, String::from_utf8_lossy(&content).into_owned()))
+}
@@ -0,0 +1,63 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::Write;
+
+/// The progress persisted by an alGlobo instance
+#[derive(Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+struct Progress {
+    last_record: usize,
+    epoch: u64,
+}
+
+/// The last processed line of an alGlobo instance kept on disk, so a restart of every instance
+/// does not process the payments again. The decisions of the lines in flight are already kept in
+/// the decision log, so the lines after the last processed one are finished whit them. It also
+/// keeps the newest epoch seen, as the microservices reject the leaders of older epochs
+pub struct Checkpoint {
+    path: String,
+    progress: Progress,
+}
+
+impl Checkpoint {
+    /// Opens the checkpoint of the instance whit the given id and path prefix, an instance that
+    /// never saved one starts from the first line
+    pub fn open(prefix: &str, id: usize) -> Checkpoint {
+        let path = format!("{}{}.json", prefix, id);
+        let progress = fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        Checkpoint { path, progress }
+    }
+
+    /// Returns the last processed line
+    pub fn last_record(&self) -> usize {
+        self.progress.last_record
+    }
+
+    /// Returns the newest epoch seen
+    pub fn epoch(&self) -> u64 {
+        self.progress.epoch
+    }
+
+    /// Persists the last processed line and the epoch if they changed. They are written to a
+    /// temporary file that replaces the checkpoint once it reaches the disk, so a crash never
+    /// leaves it torn
+    pub fn save(&mut self, last_record: usize, epoch: u64) {
+        let progress = Progress { last_record, epoch };
+        if progress == self.progress {
+            return;
+        }
+        let tmp_path = format!("{}.tmp", self.path);
+        let mut file = File::create(&tmp_path).expect("Error creating checkpoint");
+        file.write_all(
+            serde_json::to_string(&progress)
+                .expect("Error serializing checkpoint")
+                .as_bytes(),
+        )
+        .expect("Error writing checkpoint");
+        file.sync_all().expect("Error syncing checkpoint");
+        fs::rename(&tmp_path, &self.path).expect("Error replacing checkpoint");
+        self.progress = progress;
+    }
+}
@@ -0,0 +1,251 @@
+use crate::participant::{Participant, ParticipantRegistry};
+use crate::peer_directory::PeerDirectory;
+use crate::transaction_coordinator::{RetryPolicy, MAX_IN_FLIGHT};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The addresses of an alGlobo instance, as written in the configuration file
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PeerConfig {
+    pub id: usize,
+    /// Address of the socket used by the leader election
+    pub ctrl_addr: String,
+    /// Address of the socket used to replicate the progress and the decisions of the leader
+    pub data_addr: String,
+    /// Address of the socket the transaction coordinator uses to talk to the microservices
+    pub coordinator_addr: String,
+}
+
+/// The algorithm used to elect the leader among the alGlobo instances and to replicate its progress
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Election {
+    /// Bully election, the progress is sent to the followers whitout acknowledgements
+    Bully,
+    /// Chang-Roberts election on a ring ordered by id, the progress is sent as whit Bully
+    Ring,
+    /// Raft, which elects the leader and replicates the progress in a log acknowledged by a
+    /// majority of the instances
+    Raft,
+}
+
+impl FromStr for Election {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "bully" => Ok(Election::Bully),
+            "ring" => Ok(Election::Ring),
+            "raft" => Ok(Election::Raft),
+            _ => Err(format!("Unknown election {}", s)),
+        }
+    }
+}
+
+/// The first port of each socket of the default peers, the id of the peer is added to it
+const DEFAULT_CTRL_PORT: u16 = 12300;
+const DEFAULT_DATA_PORT: u16 = 12400;
+const DEFAULT_COORDINATOR_PORT: u16 = 12500;
+
+/// The timeouts of the cluster, in milliseconds
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimeoutConfig {
+    /// Time waited for an answer during an election
+    pub election_ms: u64,
+    /// Time between two heartbeats of the leader
+    pub heartbeat_interval_ms: u64,
+    /// Heartbeats in a row a follower may miss before starting an election
+    pub missed_heartbeats: u32,
+    /// Amount of times a message is sent to a microservice before deciding that it's down
+    pub max_attempts: u32,
+    /// Time waited for the microservices after the first attempt
+    pub initial_backoff_ms: u64,
+    /// Factor applied to the waiting time after each attempt
+    pub backoff_multiplier: u32,
+    /// The longest time waited between two attempts when a decision is sent until acknowledged
+    pub max_backoff_ms: u64,
+    /// Time a microservice waits for the decision of a transaction it prepared before asking
+    /// the alGlobo instances and the other microservices for it
+    pub prepared_timeout_ms: u64,
+}
+
+/// The files read and written by the binaries
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PathConfig {
+    /// The file whit the reservations to process, a CSV or a JSON array
+    pub payments: String,
+    /// The CSV where the reservations that were not committed are written
+    pub failed_transactions: String,
+    /// The path of the decision log minus the id of the alGlobo instance and the extension
+    pub decision_log: String,
+    /// The path of the microservices logs minus the name of the microservice and the extension
+    pub participant_log: String,
+    /// The path of the Raft state and log minus the id of the alGlobo instance and the extension
+    pub raft_log: String,
+    /// The path of the last processed line minus the id of the alGlobo instance and the extension
+    pub checkpoint: String,
+}
+
+/// The configuration of the whole cluster, shared by the three binaries. It's read from a TOML
+/// file or, if its extension is .json, from a JSON file. Missing sections take their defaults
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// The alGlobo instances, each one whit a different id
+    pub peers: Vec<PeerConfig>,
+    /// The microservices that take part in the transactions
+    pub microservices: Vec<Participant>,
+    /// The amount of transactions the leader processes at the same time
+    pub max_in_flight: usize,
+    /// The algorithm used to elect the leader and replicate its progress
+    pub election: Election,
+    pub timeouts: TimeoutConfig,
+    pub paths: PathConfig,
+}
+
+impl Config {
+    /// Reads the configuration from the given file
+    pub fn load(path: &str) -> Result<Config, String> {
+        let content =
+            fs::read_to_string(path).map_err(|e| format!("Error reading {}: {}", path, e))?;
+
+        let config: Config = if path.ends_with(".json") {
+            serde_json::from_str(&content).map_err(|e| format!("Invalid JSON: {}", e))?
+        } else {
+            toml::from_str(&content).map_err(|e| format!("Invalid TOML: {}", e))?
+        };
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Reads the configuration from the given file, or returns the default one if there is none
+    pub fn load_or_default(path: Option<&str>) -> Result<Config, String> {
+        match path {
+            Some(path) => Config::load(path),
+            None => Ok(Config::default()),
+        }
+    }
+
+    /// Checks the values that would keep the cluster from working, it must be called again
+    /// after changing them
+    pub fn validate(&self) -> Result<(), String> {
+        if PeerDirectory::resolve(&self.peers)?.is_empty() {
+            return Err("At least one peer is needed".to_string());
+        }
+        if self.timeouts.heartbeat_interval_ms == 0 {
+            return Err("heartbeat_interval_ms must be at least 1".to_string());
+        }
+        if self.max_in_flight == 0 {
+            return Err("max_in_flight must be at least 1".to_string());
+        }
+        if self.timeouts.max_attempts == 0 {
+            return Err("max_attempts must be at least 1".to_string());
+        }
+        let mut ids = HashSet::new();
+        for participant in &self.microservices {
+            if !ids.insert(participant.id) {
+                return Err(format!("Duplicated microservice id {}", participant.id));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails if the id is not the one of a configured alGlobo instance
+    pub fn check_peer(&self, id: usize) -> Result<(), String> {
+        if !self.peers.iter().any(|peer| peer.id == id) {
+            return Err(format!("Unknown peer {}", id));
+        }
+        Ok(())
+    }
+
+    /// Returns the directory whit the addresses of the alGlobo instances
+    pub fn directory(&self) -> PeerDirectory {
+        PeerDirectory::resolve(&self.peers).expect("Invalid peer addresses")
+    }
+
+    /// Returns the registry of the microservices
+    pub fn registry(&self) -> ParticipantRegistry {
+        ParticipantRegistry::new(self.microservices.clone())
+    }
+
+    /// Returns the time waited during an election
+    pub fn election_timeout(&self) -> Duration {
+        Duration::from_millis(self.timeouts.election_ms)
+    }
+
+    /// Returns the time between two heartbeats of the leader
+    pub fn heartbeat_interval(&self) -> Duration {
+        Duration::from_millis(self.timeouts.heartbeat_interval_ms)
+    }
+
+    /// Returns the time a microservice waits for the decision of a prepared transaction
+    pub fn prepared_timeout(&self) -> Duration {
+        Duration::from_millis(self.timeouts.prepared_timeout_ms)
+    }
+
+    /// Returns how the coordinator sends again the messages that were not answered
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.timeouts.max_attempts,
+            initial_backoff: Duration::from_millis(self.timeouts.initial_backoff_ms),
+            backoff_multiplier: self.timeouts.backoff_multiplier,
+            max_backoff: Duration::from_millis(self.timeouts.max_backoff_ms),
+        }
+    }
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        let retry_policy = RetryPolicy::default();
+        TimeoutConfig {
+            election_ms: 20_000,
+            heartbeat_interval_ms: 1_000,
+            missed_heartbeats: 3,
+            max_attempts: retry_policy.max_attempts,
+            initial_backoff_ms: retry_policy.initial_backoff.as_millis() as u64,
+            backoff_multiplier: retry_policy.backoff_multiplier,
+            max_backoff_ms: retry_policy.max_backoff.as_millis() as u64,
+            prepared_timeout_ms: 5_000,
+        }
+    }
+}
+
+impl Default for PathConfig {
+    fn default() -> Self {
+        PathConfig {
+            payments: "./resources/payments.csv".to_string(),
+            failed_transactions: "src/main/failed_transactions.csv".to_string(),
+            decision_log: "src/main/decision_log_".to_string(),
+            participant_log: "src/microservice/log_".to_string(),
+            raft_log: "src/main/raft_".to_string(),
+            checkpoint: "src/main/checkpoint_".to_string(),
+        }
+    }
+}
+
+impl Default for Config {
+    /// Five alGlobo instances and the bank, airline and hotel microservices, all in localhost
+    fn default() -> Self {
+        Config {
+            peers: (0..5)
+                .map(|id| PeerConfig {
+                    id,
+                    ctrl_addr: format!("127.0.0.1:{}", DEFAULT_CTRL_PORT + id as u16),
+                    data_addr: format!("127.0.0.1:{}", DEFAULT_DATA_PORT + id as u16),
+                    coordinator_addr: format!("127.0.0.1:{}", DEFAULT_COORDINATOR_PORT + id as u16),
+                })
+                .collect(),
+            microservices: ParticipantRegistry::default().iter().cloned().collect(),
+            max_in_flight: MAX_IN_FLIGHT,
+            election: Election::Bully,
+            timeouts: TimeoutConfig::default(),
+            paths: PathConfig::default(),
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use crate::append_log;
+use crate::reservation::Reservation;
+use crate::transaction::TransactionState;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::Write;
+
+/// The first byte of a serialized decision
+pub const DECISION_HEADER: u8 = b'D';
+
+/// A single entry of the decision log: the state the coordinator reached for a transaction
+/// together with the reservation it belongs to, so the transaction can be finished after a crash
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Decision {
+    pub transaction_id: i32,
+    pub state: TransactionState,
+    pub reservation: Reservation,
+}
+
+impl Decision {
+    /// Converts the decision into a bytes array to be replicated to the other alGlobo instances,
+    /// it starts with DECISION_HEADER so it can be told apart from other messages, followed by
+    /// the id of the sender and its election epoch
+    pub fn serialize(&self, sender: usize, epoch: u64) -> Vec<u8> {
+        let mut serialize = vec![DECISION_HEADER];
+        serialize.extend_from_slice(&(sender as u64).to_be_bytes());
+        serialize.extend_from_slice(&epoch.to_be_bytes());
+        serialize.extend_from_slice(self.to_line().as_bytes());
+        serialize
+    }
+
+    /// Converts a serialized decision into the id of its sender, its epoch and the decision
+    /// again, returns None if it's malformed
+    pub fn deserialize(buf: &[u8]) -> Option<(usize, u64, Decision)> {
+        let sender = u64::from_be_bytes(buf.get(1..9)?.try_into().ok()?) as usize;
+        let epoch = u64::from_be_bytes(buf.get(9..17)?.try_into().ok()?);
+        let line = std::str::from_utf8(buf.get(17..)?).ok()?;
+        Some((sender, epoch, Decision::from_line(line)?))
+    }
+
+    /// Converts the decision into a line of the log file, written as JSON
+    fn to_line(&self) -> String {
+        let mut line = serde_json::to_string(self).expect("Error serializing decision");
+        line.push('\n');
+        line
+    }
+
+    /// Parses a line of the log file, returns None if the line is corrupt (e.g. a torn write)
+    fn from_line(line: &str) -> Option<Decision> {
+        serde_json::from_str(line.trim()).ok()
+    }
+}
+
+/// Append only log where the coordinator persists every decision before acting on it
+pub struct DecisionLog {
+    file: File,
+    decisions: HashMap<i32, Decision>,
+    finished: HashSet<i32>,
+}
+
+impl DecisionLog {
+    /// Opens the log at the given path, replaying the decisions it already contains. The torn
+    /// entry a crash may have left at the end is dropped
+    pub fn open(path: &str) -> DecisionLog {
+        let (file, content) = append_log::open(path).expect("Error opening decision log");
+        let mut log = DecisionLog {
+            file,
+            decisions: HashMap::new(),
+            finished: HashSet::new(),
+        };
+
+        for line in content.lines() {
+            match Decision::from_line(line) {
+                Some(decision) => log.apply(decision),
+                None => println!("[DECISION LOG] skipping corrupt entry {}", line),
+            }
+        }
+
+        log
+    }
+
+    /// Returns the last state logged for the transaction
+    pub fn get(&self, t: i32) -> Option<TransactionState> {
+        self.decisions.get(&t).map(|decision| decision.state)
+    }
+
+    /// Persists the decision and waits until it reaches the disk
+    pub fn record(&mut self, decision: Decision) {
+        self.file
+            .write_all(decision.to_line().as_bytes())
+            .expect("Error writing decision log");
+        self.file.sync_data().expect("Error syncing decision log");
+        self.apply(decision);
+    }
+
+    /// Returns the greatest transaction id in the log, None if it's empty
+    pub fn last_transaction_id(&self) -> Option<i32> {
+        self.decisions.keys().max().copied()
+    }
+
+    /// Returns the decisions of the transactions that were not acknowledged by every microservice
+    pub fn in_doubt(&self) -> Vec<Decision> {
+        let mut in_doubt: Vec<Decision> = self
+            .decisions
+            .values()
+            .filter(|decision| !self.finished.contains(&decision.transaction_id))
+            .cloned()
+            .collect();
+        in_doubt.sort_by_key(|decision| decision.transaction_id);
+        in_doubt
+    }
+
+    /// Updates the in memory state whit the decision
+    fn apply(&mut self, decision: Decision) {
+        if decision.state == TransactionState::Finished {
+            self.finished.insert(decision.transaction_id);
+        } else {
+            self.finished.remove(&decision.transaction_id);
+            self.decisions.insert(decision.transaction_id, decision);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::Currency;
+    use std::fs;
+
+    fn decision(transaction_id: i32, state: TransactionState) -> Decision {
+        Decision {
+            transaction_id,
+            state,
+            reservation: Reservation {
+                line: transaction_id as usize,
+                customer_id: String::new(),
+                currency: Currency::default(),
+                legs: Vec::new(),
+            },
+        }
+    }
+
+    fn log_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("{}_{}.log", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path.to_str()
+            .expect("Temporary path is not UTF-8")
+            .to_string()
+    }
+
+    #[test]
+    fn decisions_are_replayed() {
+        let path = log_path("decision_log_replay");
+        let mut log = DecisionLog::open(&path);
+        log.record(decision(1, TransactionState::Wait));
+        log.record(decision(1, TransactionState::Commit));
+        log.record(decision(2, TransactionState::Abort));
+        log.record(decision(2, TransactionState::Finished));
+
+        let log = DecisionLog::open(&path);
+        assert_eq!(log.get(1), Some(TransactionState::Commit));
+        assert_eq!(log.get(2), Some(TransactionState::Abort));
+        let in_doubt: Vec<i32> = log.in_doubt().iter().map(|d| d.transaction_id).collect();
+        assert_eq!(in_doubt, vec![1]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn decisions_after_a_torn_write_are_replayed() {
+        let path = log_path("decision_log_torn");
+        let mut log = DecisionLog::open(&path);
+        log.record(decision(1, TransactionState::Commit));
+        drop(log);
+        let line = decision(2, TransactionState::Commit).to_line();
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&line.as_bytes()[..line.len() / 2]).unwrap();
+        drop(file);
+
+        let mut log = DecisionLog::open(&path);
+        assert_eq!(log.get(2), None);
+        log.record(decision(3, TransactionState::Abort));
+
+        let log = DecisionLog::open(&path);
+        assert_eq!(log.get(1), Some(TransactionState::Commit));
+        assert_eq!(log.get(2), None);
+        assert_eq!(log.get(3), Some(TransactionState::Abort));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,4 +1,12 @@
-pub mod helper;
-pub mod payment;
+pub mod append_log;
+pub mod checkpoint;
+pub mod config;
+pub mod decision_log;
+pub mod money;
+pub mod participant;
+pub mod peer_directory;
+pub mod replication;
+pub mod reservation;
 pub mod transaction;
 pub mod transaction_coordinator;
+pub mod transaction_outcome;
@@ -0,0 +1,327 @@
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+
+/// The currency used when none is specified
+pub const DEFAULT_CURRENCY: &str = "ARS";
+/// Currencies whose amounts have no minor units
+const ZERO_DECIMAL_CURRENCIES: [&str; 4] = ["CLP", "JPY", "KRW", "PYG"];
+/// Digits of the minor units of every other currency
+const DEFAULT_MINOR_DIGITS: u32 = 2;
+
+/// The reasons why an operation over money can fail
+#[derive(Debug, PartialEq, Eq)]
+pub enum MoneyError {
+    /// The result does not fit in the minor units
+    Overflow,
+    /// The operation mixes amounts in different currencies
+    CurrencyMismatch(Currency, Currency),
+    /// The amount could not be parsed
+    InvalidAmount(String),
+    /// The currency is not a three letters ISO 4217 code
+    InvalidCurrency(String),
+}
+
+impl fmt::Display for MoneyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoneyError::Overflow => write!(f, "amount overflow"),
+            MoneyError::CurrencyMismatch(a, b) => write!(f, "can not mix {} and {}", a, b),
+            MoneyError::InvalidAmount(amount) => write!(f, "invalid amount {}", amount),
+            MoneyError::InvalidCurrency(code) => write!(f, "invalid currency {}", code),
+        }
+    }
+}
+
+/// An ISO 4217 currency code
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    /// Creates the currency from its code, it must be formed by three ASCII letters
+    pub fn new(code: &str) -> Result<Currency, MoneyError> {
+        let code = code.trim().to_ascii_uppercase();
+        let bytes = code.as_bytes();
+        if bytes.len() != 3 || !bytes.iter().all(u8::is_ascii_uppercase) {
+            return Err(MoneyError::InvalidCurrency(code));
+        }
+        Ok(Currency([bytes[0], bytes[1], bytes[2]]))
+    }
+
+    /// Returns the code of the currency
+    pub fn code(&self) -> &str {
+        std::str::from_utf8(&self.0).expect("Currency code is always ASCII")
+    }
+
+    /// Returns the code of the currency as bytes, to be sent through a socket
+    pub fn to_bytes(self) -> [u8; 3] {
+        self.0
+    }
+
+    /// Returns the amount of digits of the minor units of the currency
+    pub fn minor_digits(&self) -> u32 {
+        if ZERO_DECIMAL_CURRENCIES.contains(&self.code()) {
+            0
+        } else {
+            DEFAULT_MINOR_DIGITS
+        }
+    }
+}
+
+impl Default for Currency {
+    /// The default currency, ARS
+    fn default() -> Self {
+        Currency::new(DEFAULT_CURRENCY).expect("Invalid default currency")
+    }
+}
+
+impl TryFrom<String> for Currency {
+    type Error = MoneyError;
+
+    fn try_from(code: String) -> Result<Self, Self::Error> {
+        Currency::new(&code)
+    }
+}
+
+impl From<Currency> for String {
+    fn from(currency: Currency) -> Self {
+        currency.code().to_string()
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+/// An exact amount of money, kept as minor units (e.g. cents) of its currency
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct Money {
+    minor_units: i64,
+    currency: Currency,
+}
+
+impl Money {
+    /// Creates an amount from its minor units
+    pub fn new(minor_units: i64, currency: Currency) -> Money {
+        Money {
+            minor_units,
+            currency,
+        }
+    }
+
+    /// Creates a zero amount in the currency
+    pub fn zero(currency: Currency) -> Money {
+        Money::new(0, currency)
+    }
+
+    /// Creates an amount from its major units (e.g. whole pesos), which can not be negative
+    pub fn from_major(major_units: i64, currency: Currency) -> Result<Money, MoneyError> {
+        if major_units < 0 {
+            return Err(MoneyError::InvalidAmount(major_units.to_string()));
+        }
+        major_units
+            .checked_mul(10_i64.pow(currency.minor_digits()))
+            .map(|minor_units| Money::new(minor_units, currency))
+            .ok_or(MoneyError::Overflow)
+    }
+
+    /// Parses a decimal amount written in major units, such as 1500 or 1500.25. Negative
+    /// amounts are rejected, as nobody is charged them
+    pub fn parse(amount: &str, currency: Currency) -> Result<Money, MoneyError> {
+        let amount = amount.trim();
+        let invalid = || MoneyError::InvalidAmount(amount.to_string());
+        let (major, minor) = match amount.split_once('.') {
+            Some((major, minor)) => (major, minor),
+            None => (amount, ""),
+        };
+
+        let minor_digits = currency.minor_digits() as usize;
+        let all_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+        if major.is_empty() || !all_digits(major) || !all_digits(minor) {
+            return Err(invalid());
+        }
+        if minor.len() > minor_digits {
+            return Err(invalid());
+        }
+
+        let major: i64 = major.parse().map_err(|_| MoneyError::Overflow)?;
+        let minor: i64 = format!("{:0<width$}", minor, width = minor_digits)
+            .parse()
+            .unwrap_or(0);
+        let minor_units = major
+            .checked_mul(10_i64.pow(minor_digits as u32))
+            .and_then(|units| units.checked_add(minor))
+            .ok_or(MoneyError::Overflow)?;
+
+        Ok(Money::new(minor_units, currency))
+    }
+
+    /// Returns the amount in minor units
+    pub fn minor_units(&self) -> i64 {
+        self.minor_units
+    }
+
+    /// Returns the amount in whole major units, truncating the minor units
+    pub fn major_units(&self) -> i64 {
+        self.minor_units / 10_i64.pow(self.currency.minor_digits())
+    }
+
+    /// Returns the currency of the amount
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    /// Returns true if the amount is greater than zero
+    pub fn is_positive(&self) -> bool {
+        self.minor_units > 0
+    }
+
+    /// Adds both amounts, failing if the currencies differ or the result overflows
+    pub fn checked_add(self, other: Money) -> Result<Money, MoneyError> {
+        self.same_currency(other)?;
+        self.minor_units
+            .checked_add(other.minor_units)
+            .map(|minor_units| Money::new(minor_units, self.currency))
+            .ok_or(MoneyError::Overflow)
+    }
+
+    /// Returns the amount in major units without the currency, such as 1500.25
+    pub fn to_decimal_string(&self) -> String {
+        let digits = self.currency.minor_digits();
+        if digits == 0 {
+            return self.minor_units.to_string();
+        }
+        let scale = 10_u64.pow(digits);
+        let units = self.minor_units.unsigned_abs();
+        format!(
+            "{}{}.{:0width$}",
+            if self.minor_units < 0 { "-" } else { "" },
+            units / scale,
+            units % scale,
+            width = digits as usize
+        )
+    }
+
+    /// Fails if the other amount is in a different currency
+    fn same_currency(&self, other: Money) -> Result<(), MoneyError> {
+        if self.currency != other.currency {
+            return Err(MoneyError::CurrencyMismatch(self.currency, other.currency));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.to_decimal_string(), self.currency)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ars() -> Currency {
+        Currency::new("ARS").unwrap()
+    }
+
+    fn jpy() -> Currency {
+        Currency::new("JPY").unwrap()
+    }
+
+    #[test]
+    fn parse_reads_major_and_minor_units() {
+        assert_eq!(Money::parse("1500", ars()), Ok(Money::new(150000, ars())));
+        assert_eq!(
+            Money::parse("1500.25", ars()),
+            Ok(Money::new(150025, ars()))
+        );
+        assert_eq!(Money::parse("1500.5", ars()), Ok(Money::new(150050, ars())));
+        assert_eq!(Money::parse(" 12 ", ars()), Ok(Money::new(1200, ars())));
+        assert_eq!(Money::parse("1500", jpy()), Ok(Money::new(1500, jpy())));
+    }
+
+    #[test]
+    fn parse_rejects_malformed_amounts() {
+        for amount in ["", ".", ".5", "1.2.3", "1,5", "abc", "+5", "1e3", "1.234"] {
+            assert_eq!(
+                Money::parse(amount, ars()),
+                Err(MoneyError::InvalidAmount(amount.to_string())),
+                "{}",
+                amount
+            );
+        }
+        assert_eq!(
+            Money::parse("1.5", jpy()),
+            Err(MoneyError::InvalidAmount("1.5".to_string()))
+        );
+    }
+
+    #[test]
+    fn parse_rejects_negative_amounts() {
+        assert_eq!(
+            Money::parse("-5", ars()),
+            Err(MoneyError::InvalidAmount("-5".to_string()))
+        );
+        assert_eq!(
+            Money::parse("-0.01", ars()),
+            Err(MoneyError::InvalidAmount("-0.01".to_string()))
+        );
+        assert_eq!(
+            Money::from_major(-5, ars()),
+            Err(MoneyError::InvalidAmount("-5".to_string()))
+        );
+    }
+
+    #[test]
+    fn overflow_is_reported() {
+        assert_eq!(
+            Money::parse("99999999999999999999", ars()),
+            Err(MoneyError::Overflow)
+        );
+        assert_eq!(
+            Money::parse(&(i64::MAX / 10).to_string(), ars()),
+            Err(MoneyError::Overflow)
+        );
+        assert_eq!(
+            Money::from_major(i64::MAX, ars()),
+            Err(MoneyError::Overflow)
+        );
+        assert_eq!(
+            Money::new(i64::MAX, ars()).checked_add(Money::new(1, ars())),
+            Err(MoneyError::Overflow)
+        );
+    }
+
+    #[test]
+    fn currencies_can_not_be_mixed() {
+        assert_eq!(
+            Money::new(1, ars()).checked_add(Money::new(1, jpy())),
+            Err(MoneyError::CurrencyMismatch(ars(), jpy()))
+        );
+        assert_eq!(
+            Money::new(1, ars()).checked_add(Money::new(2, ars())),
+            Ok(Money::new(3, ars()))
+        );
+    }
+
+    #[test]
+    fn currency_codes_are_three_letters() {
+        assert_eq!(
+            Currency::new(" usd ").map(|c| c.to_string()),
+            Ok("USD".to_string())
+        );
+        assert!(Currency::new("US").is_err());
+        assert!(Currency::new("U5D").is_err());
+    }
+
+    #[test]
+    fn decimal_string_keeps_the_minor_digits() {
+        assert_eq!(Money::new(150005, ars()).to_decimal_string(), "1500.05");
+        assert_eq!(Money::new(-5, ars()).to_decimal_string(), "-0.05");
+        assert_eq!(Money::new(1500, jpy()).to_string(), "1500 JPY");
+    }
+}
@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+
+/// A microservice that takes part in the transactions
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Participant {
+    pub id: usize,
+    pub name: String,
+    pub address: String,
+}
+
+/// The set of microservices the coordinator talks to. The name of each participant is the column
+/// of the payments CSV that holds the amount it's charged
+#[derive(Clone)]
+pub struct ParticipantRegistry {
+    participants: Vec<Participant>,
+}
+
+impl ParticipantRegistry {
+    /// Creates a registry whit the given participants
+    pub fn new(participants: Vec<Participant>) -> ParticipantRegistry {
+        ParticipantRegistry { participants }
+    }
+
+    /// Returns the participant whit the given id
+    pub fn get(&self, id: usize) -> Option<&Participant> {
+        self.participants
+            .iter()
+            .find(|participant| participant.id == id)
+    }
+
+    /// Returns the participant whit the given name, the comparison ignores case
+    pub fn by_name(&self, name: &str) -> Option<&Participant> {
+        self.participants
+            .iter()
+            .find(|participant| participant.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Returns the name of the participant whit the given id, or the id itself if it's unknown
+    pub fn name_of(&self, id: usize) -> String {
+        match self.get(id) {
+            Some(participant) => participant.name.clone(),
+            None => id.to_string(),
+        }
+    }
+
+    /// Iterates over the participants
+    pub fn iter(&self) -> impl Iterator<Item = &Participant> {
+        self.participants.iter()
+    }
+}
+
+impl Default for ParticipantRegistry {
+    /// The bank, airline and hotel microservices
+    fn default() -> Self {
+        ParticipantRegistry::new(vec![
+            Participant {
+                id: 0,
+                name: "Bank".to_string(),
+                address: "127.0.0.1:1111".to_string(),
+            },
+            Participant {
+                id: 1,
+                name: "Airline".to_string(),
+                address: "127.0.0.1:2222".to_string(),
+            },
+            Participant {
+                id: 2,
+                name: "Hotel".to_string(),
+                address: "127.0.0.1:3333".to_string(),
+            },
+        ])
+    }
+}
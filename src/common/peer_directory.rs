@@ -0,0 +1,85 @@
+use crate::config::PeerConfig;
+use std::collections::BTreeMap;
+use std::net::{SocketAddr, ToSocketAddrs};
+
+/// The sockets of an alGlobo instance
+#[derive(Copy, Clone, Debug)]
+pub struct PeerAddrs {
+    /// Address of the socket used by the leader election
+    pub ctrl: SocketAddr,
+    /// Address of the socket used to replicate the progress and the decisions of the leader
+    pub data: SocketAddr,
+    /// Address of the socket the transaction coordinator uses to talk to the microservices
+    pub coordinator: SocketAddr,
+}
+
+/// Maps the id of every alGlobo instance to its addresses. Messages carry the id of their sender,
+/// so the directory is only used to know where to send them
+#[derive(Clone)]
+pub struct PeerDirectory {
+    peers: BTreeMap<usize, PeerAddrs>,
+}
+
+impl PeerDirectory {
+    /// Resolves the addresses of the configured peers, failing if an address is invalid or an
+    /// id is repeated
+    pub fn resolve(peers: &[PeerConfig]) -> Result<PeerDirectory, String> {
+        let mut directory = PeerDirectory {
+            peers: BTreeMap::new(),
+        };
+        for peer in peers {
+            let addrs = PeerAddrs {
+                ctrl: resolve_addr(&peer.ctrl_addr)?,
+                data: resolve_addr(&peer.data_addr)?,
+                coordinator: resolve_addr(&peer.coordinator_addr)?,
+            };
+            if directory.peers.insert(peer.id, addrs).is_some() {
+                return Err(format!("Peer {} is repeated", peer.id));
+            }
+        }
+        Ok(directory)
+    }
+
+    /// Returns the addresses of the peer whit the given id
+    pub fn get(&self, id: usize) -> Option<&PeerAddrs> {
+        self.peers.get(&id)
+    }
+
+    /// Returns the amount of peers
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    /// Returns true if there are no peers
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// Iterates over the ids of the peers in ascending order
+    pub fn ids(&self) -> impl Iterator<Item = usize> + '_ {
+        self.peers.keys().copied()
+    }
+
+    /// Iterates over every peer but the one whit the given id
+    pub fn others(&self, id: usize) -> impl Iterator<Item = (usize, &PeerAddrs)> + '_ {
+        self.peers
+            .iter()
+            .filter(move |(peer_id, _)| **peer_id != id)
+            .map(|(peer_id, addrs)| (*peer_id, addrs))
+    }
+
+    /// Iterates over the peers whose id is greater than the given one
+    pub fn above(&self, id: usize) -> impl Iterator<Item = (usize, &PeerAddrs)> + '_ {
+        self.peers
+            .range(id + 1..)
+            .map(|(peer_id, addrs)| (*peer_id, addrs))
+    }
+}
+
+/// Resolves an address such as 127.0.0.1:12300 or localhost:12300
+fn resolve_addr(addr: &str) -> Result<SocketAddr, String> {
+    addr.to_socket_addrs()
+        .map_err(|e| format!("Invalid address {}: {}", addr, e))?
+        .next()
+        .ok_or(format!("Address {} does not resolve", addr))
+}
@@ -0,0 +1,43 @@
+use crate::decision_log::Decision;
+use std::net::{SocketAddr, UdpSocket};
+
+/// Sends the decisions recorded by the coordinator to the other alGlobo instances, so the next
+/// leader can finish the transactions left in doubt
+pub trait Replicator: Send + Sync {
+    /// Replicates a decision recorded by the leader of the given epoch, returns an error if it
+    /// may not reach the next leader
+    fn replicate(&self, decision: &Decision, epoch: u64) -> Result<(), String>;
+}
+
+/// Replicates the decisions by sending them through a socket to every other alGlobo instance,
+/// whitout waiting for any acknowledgement
+pub struct UdpReplicator {
+    id: usize,
+    socket: UdpSocket,
+    peers: Vec<SocketAddr>,
+}
+
+impl UdpReplicator {
+    /// Creates a replicator that sends the decisions of the instance whit the given id to the
+    /// given addresses
+    pub fn new(id: usize, socket: UdpSocket, peers: Vec<SocketAddr>) -> UdpReplicator {
+        UdpReplicator { id, socket, peers }
+    }
+}
+
+impl Replicator for UdpReplicator {
+    fn replicate(&self, decision: &Decision, epoch: u64) -> Result<(), String> {
+        for peer in &self.peers {
+            if let Err(e) = self
+                .socket
+                .send_to(&decision.serialize(self.id, epoch), peer)
+            {
+                println!(
+                    "[COORDINATOR] error replicating {} to {}: {}",
+                    decision.transaction_id, peer, e
+                );
+            }
+        }
+        Ok(())
+    }
+}
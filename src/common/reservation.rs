@@ -0,0 +1,248 @@
+use crate::money::{Currency, Money, MoneyError};
+use crate::participant::ParticipantRegistry;
+use csv::StringRecord;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+
+/// The part of a reservation handled by a single service
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Leg {
+    pub service: usize,
+    pub amount: Money,
+    pub reference: String,
+}
+
+/// A reservation of a package, formed by the legs of the services it includes. Each entry of
+/// the payments file is a reservation
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Reservation {
+    pub line: usize,
+    pub customer_id: String,
+    pub currency: Currency,
+    pub legs: Vec<Leg>,
+}
+
+/// An amount as written in the JSON payments file, either a whole number or a decimal string
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonAmount {
+    Units(i64),
+    Decimal(String),
+}
+
+/// A leg as written in the JSON payments file, the service is referenced by its name
+#[derive(Deserialize)]
+struct JsonLeg {
+    service: String,
+    amount: JsonAmount,
+    #[serde(default)]
+    reference: String,
+}
+
+/// A reservation as written in the JSON payments file
+#[derive(Deserialize)]
+struct JsonReservation {
+    line: usize,
+    #[serde(default)]
+    customer_id: String,
+    currency: Option<String>,
+    legs: Vec<JsonLeg>,
+}
+
+impl Reservation {
+    /// Builds a reservation from a CSV record. Besides the line, customer_id and currency columns,
+    /// a column named after a participant holds the decimal amount of its leg and a column named
+    /// after a participant followed by _reference holds the reference of its leg. Legs whit an
+    /// empty amount are not part of the reservation
+    pub fn from_record(
+        headers: &StringRecord,
+        record: &StringRecord,
+        registry: &ParticipantRegistry,
+    ) -> Result<Reservation, String> {
+        // The amounts are parsed in the currency of the record, wherever its column is
+        let currency = match headers.iter().position(|header| header == "currency") {
+            Some(position) => match record.get(position).map(str::trim) {
+                Some(code) if !code.is_empty() => Currency::new(code).map_err(|e| e.to_string())?,
+                _ => Currency::default(),
+            },
+            None => Currency::default(),
+        };
+        let mut reservation = Reservation {
+            line: 0,
+            customer_id: String::new(),
+            currency,
+            legs: Vec::new(),
+        };
+        let mut line = None;
+
+        for (header, field) in headers.iter().zip(record.iter()) {
+            let field = field.trim();
+            match header {
+                "line" => {
+                    line = Some(
+                        field
+                            .parse()
+                            .map_err(|e| format!("Invalid line {}: {}", field, e))?,
+                    );
+                }
+                "customer_id" => reservation.customer_id = field.to_string(),
+                "currency" => {}
+                _ => {
+                    if header.ends_with("_reference") || field.is_empty() {
+                        continue;
+                    }
+                    let participant = registry
+                        .by_name(header)
+                        .ok_or(format!("Unknown participant {}", header))?;
+                    let reference = headers
+                        .iter()
+                        .position(|h| h == format!("{}_reference", header))
+                        .and_then(|position| record.get(position))
+                        .unwrap_or("")
+                        .trim()
+                        .to_string();
+                    reservation.legs.push(Leg {
+                        service: participant.id,
+                        amount: Money::parse(field, currency)
+                            .map_err(|e| format!("{} for {}", e, header))?,
+                        reference,
+                    });
+                }
+            }
+        }
+
+        reservation.line = line.ok_or("Missing line column")?;
+        reservation.validate(registry)?;
+        Ok(reservation)
+    }
+
+    /// Fails if a service has more than one leg, as only one amount is sent to each service,
+    /// or if the total overflows
+    fn validate(&self, registry: &ParticipantRegistry) -> Result<(), String> {
+        let mut services = HashSet::new();
+        for leg in &self.legs {
+            if !services.insert(leg.service) {
+                return Err(format!(
+                    "Duplicated leg for {}",
+                    registry.name_of(leg.service)
+                ));
+            }
+        }
+        self.total().map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// Returns the leg of the service, if it takes part in the reservation
+    pub fn leg_for(&self, service: usize) -> Option<&Leg> {
+        self.legs.iter().find(|leg| leg.service == service)
+    }
+
+    /// Returns the ids of the services that take part in the reservation
+    pub fn services(&self) -> Vec<usize> {
+        self.legs.iter().map(|leg| leg.service).collect()
+    }
+
+    /// Returns the sum of the amounts of every leg, failing if it overflows
+    pub fn total(&self) -> Result<Money, MoneyError> {
+        self.legs
+            .iter()
+            .try_fold(Money::zero(self.currency), |total, leg| {
+                total.checked_add(leg.amount)
+            })
+    }
+
+    /// Returns the decimal amount of every leg next to the name of its service, separated by
+    /// semicolons, such as Bank=100.00;Hotel=300.00
+    pub fn leg_amounts(&self, registry: &ParticipantRegistry) -> String {
+        self.legs
+            .iter()
+            .map(|leg| {
+                format!(
+                    "{}={}",
+                    registry.name_of(leg.service),
+                    leg.amount.to_decimal_string()
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(";")
+    }
+}
+
+/// An entry of the payments file: its line, unless not even the line could be read, and the
+/// reservation or why it could not be read
+pub type PaymentEntry = (Option<usize>, Result<Reservation, String>);
+
+/// Reads every reservation of the payments file, which may be a CSV or, if its extension is
+/// .json, a JSON array of reservations. Entries that can not be read are returned as errors,
+/// together whit their line so they can still be counted as processed
+pub fn read_reservations(
+    path: &str,
+    registry: &ParticipantRegistry,
+) -> Result<Vec<PaymentEntry>, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("Error reading {}: {}", path, e))?;
+
+    if path.ends_with(".json") {
+        let reservations: Vec<JsonReservation> =
+            serde_json::from_str(&content).map_err(|e| format!("Invalid JSON: {}", e))?;
+        return Ok(reservations
+            .into_iter()
+            .map(|reservation| (Some(reservation.line), from_json(reservation, registry)))
+            .collect());
+    }
+
+    let mut reader = csv::Reader::from_reader(content.as_bytes());
+    let headers = reader
+        .headers()
+        .map_err(|e| format!("Invalid CSV headers: {}", e))?
+        .clone();
+    let line_column = headers.iter().position(|header| header == "line");
+    Ok(reader
+        .into_records()
+        .map(|record| match record {
+            Ok(record) => {
+                let line = line_column
+                    .and_then(|position| record.get(position))
+                    .and_then(|field| field.trim().parse().ok());
+                (line, Reservation::from_record(&headers, &record, registry))
+            }
+            Err(e) => (None, Err(e.to_string())),
+        })
+        .collect())
+}
+
+/// Converts a reservation read from JSON, whose legs reference services by name
+fn from_json(
+    reservation: JsonReservation,
+    registry: &ParticipantRegistry,
+) -> Result<Reservation, String> {
+    let currency = match reservation.currency {
+        Some(code) => Currency::new(&code).map_err(|e| e.to_string())?,
+        None => Currency::default(),
+    };
+
+    let mut legs = Vec::new();
+    for leg in reservation.legs {
+        let participant = registry
+            .by_name(&leg.service)
+            .ok_or(format!("Unknown participant {}", leg.service))?;
+        let amount = match leg.amount {
+            JsonAmount::Units(units) => Money::from_major(units, currency),
+            JsonAmount::Decimal(ref amount) => Money::parse(amount, currency),
+        };
+        legs.push(Leg {
+            service: participant.id,
+            amount: amount.map_err(|e| format!("{} for {}", e, leg.service))?,
+            reference: leg.reference,
+        });
+    }
+
+    let reservation = Reservation {
+        line: reservation.line,
+        customer_id: reservation.customer_id,
+        currency,
+        legs,
+    };
+    reservation.validate(registry)?;
+    Ok(reservation)
+}
@@ -1,73 +1,433 @@
+use crate::money::{Currency, Money};
+use serde::{Deserialize, Serialize};
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+
 /// This enum represent all possible states of a transaction
-#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 pub enum TransactionState {
     Accepted,
     Commit,
     Abort,
     Prepare,
     Wait,
+    Finished,
+    /// Asks a microservice what it knows of a transaction, sent by a new leader for the
+    /// transactions the previous one may have left in flight
+    Query,
 }
 
 /// This struct is made to represent a transaction between the leader and a microservice. It's
-/// formed by the transaction_state(TransactionState), transaction_id, amount, and service(as it's id)
+/// formed by the transaction_state(TransactionState), transaction_id, amount, service(as it's id),
+/// the phase of the protocol the message belongs to (Prepare, Commit, Abort or Query), the
+/// reference of the leg of the reservation handled by the service and the election epoch of the
+/// leader that sent it
 pub struct Transaction {
     pub transaction_state: TransactionState,
     pub transaction_id: i32,
-    pub amount: i32,
+    pub amount: Money,
     pub service: i32,
+    pub phase: TransactionState,
+    pub reference: String,
+    pub epoch: u64,
 }
 
-impl Transaction {
-    /// Converts the transaction into a bytes array to be send through a socket
-    pub fn serialize(&mut self) -> [u8; 13] {
-        let mut serialize: [u8; 13] = [0; 13];
-
-        serialize[0] = match self.transaction_state {
-            TransactionState::Prepare => b'P',
-            TransactionState::Abort => b'A',
-            TransactionState::Commit => b'C',
-            _ => {
-                panic!("Unrecognized TransactionState")
-            }
-        };
+/// The bytes every frame starts with
+const MAGIC: [u8; 2] = *b"AG";
+/// The version of the protocol used to build frames
+pub const PROTOCOL_VERSION: u8 = 5;
+/// The size of the header: magic, version and payload length
+const HEADER_SIZE: usize = 5;
+/// The size of the checksum that closes every frame
+const CHECKSUM_SIZE: usize = 4;
+/// The size of the payload of a version 1 frame
+const PAYLOAD_V1_SIZE: usize = 13;
+/// The size of the payload of a version 2 frame, which adds the phase
+const PAYLOAD_V2_SIZE: usize = 14;
+/// The size of the exact amount appended by version 4: minor units and currency code
+const MONEY_SIZE: usize = 11;
+/// The size of the election epoch appended by version 5
+const EPOCH_SIZE: usize = 8;
+/// The longest reference that is sent, longer ones are truncated
+pub const MAX_REFERENCE_SIZE: usize = 256;
+/// The biggest frame that can be received
+pub const MAX_FRAME_SIZE: usize = 512;
 
-        let bin_transaction_id = self.transaction_id.to_le_bytes();
-        serialize[1..5].copy_from_slice(&bin_transaction_id);
+impl Transaction {
+    /// Converts the transaction into a frame to be send through a socket. The frame is formed by
+    /// the magic number, the protocol version, the length of the payload, the payload and a
+    /// checksum of everything before it. The exact amount is appended at the end of the payload,
+    /// the old amount field keeps its whole major units (saturated) for older readers
+    pub fn serialize(&mut self) -> Vec<u8> {
+        // The reference is truncated at a char boundary, so it's still valid UTF-8
+        let mut reference_end = self.reference.len().min(MAX_REFERENCE_SIZE);
+        while !self.reference.is_char_boundary(reference_end) {
+            reference_end -= 1;
+        }
+        let reference = &self.reference.as_bytes()[..reference_end];
+        let mut payload: Vec<u8> =
+            Vec::with_capacity(PAYLOAD_V2_SIZE + 2 + reference.len() + MONEY_SIZE + EPOCH_SIZE);
+        let legacy_amount = self
+            .amount
+            .major_units()
+            .clamp(i32::MIN as i64, i32::MAX as i64) as i32;
 
-        let bin_amount = self.amount.to_le_bytes();
-        serialize[5..9].copy_from_slice(&bin_amount);
+        payload.push(state_to_byte(self.transaction_state));
+        payload.extend_from_slice(&self.transaction_id.to_le_bytes());
+        payload.extend_from_slice(&legacy_amount.to_le_bytes());
+        payload.extend_from_slice(&self.service.to_le_bytes());
+        payload.push(state_to_byte(self.phase));
+        payload.extend_from_slice(&(reference.len() as u16).to_le_bytes());
+        payload.extend_from_slice(reference);
+        payload.extend_from_slice(&self.amount.minor_units().to_le_bytes());
+        payload.extend_from_slice(&self.amount.currency().to_bytes());
+        payload.extend_from_slice(&self.epoch.to_le_bytes());
 
-        let bin_service = self.service.to_le_bytes();
-        serialize[9..13].copy_from_slice(&bin_service);
+        let mut serialize = Vec::with_capacity(HEADER_SIZE + payload.len() + CHECKSUM_SIZE);
+        serialize.extend_from_slice(&MAGIC);
+        serialize.push(PROTOCOL_VERSION);
+        serialize.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+        serialize.extend_from_slice(&payload);
+        let checksum = crc32(&serialize);
+        serialize.extend_from_slice(&checksum.to_le_bytes());
 
         serialize
     }
+}
 
-    /// Converts serialized transaction into a transaction again
-    pub fn deserialize(buf: [u8; 13]) -> Transaction {
-        let state = match buf[0] {
-            b'P' => TransactionState::Prepare,
-            b'C' => TransactionState::Commit,
-            b'A' => TransactionState::Abort,
-            _ => {
-                panic!("Invalid transaction state")
+/// The reasons why a frame can not be converted into a transaction
+#[derive(Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The frame is shorter than its header or than the length it declares
+    ShortBuffer,
+    /// The frame does not start with the magic number
+    BadMagic,
+    /// The frame was built whit a version of the protocol that is not supported
+    UnsupportedVersion(u8),
+    /// The checksum does not match the content of the frame
+    BadChecksum,
+    /// The byte that represents the state of the transaction is unknown
+    BadState(u8),
+    /// The currency of the amount is not a valid ISO 4217 code
+    BadCurrency,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::ShortBuffer => write!(f, "frame is too short"),
+            DecodeError::BadMagic => write!(f, "frame has a wrong magic number"),
+            DecodeError::UnsupportedVersion(version) => {
+                write!(f, "protocol version {} is not supported", version)
             }
+            DecodeError::BadChecksum => write!(f, "frame checksum does not match"),
+            DecodeError::BadState(state) => write!(f, "invalid transaction state {}", state),
+            DecodeError::BadCurrency => write!(f, "invalid currency"),
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for Transaction {
+    type Error = DecodeError;
+
+    /// Converts a frame into a transaction again. Only the versions up to PROTOCOL_VERSION are
+    /// supported, the fields appended by each version are read if the frame carries them
+    fn try_from(buf: &[u8]) -> Result<Self, Self::Error> {
+        if buf.len() < HEADER_SIZE {
+            return Err(DecodeError::ShortBuffer);
+        }
+        if buf[0..2] != MAGIC {
+            return Err(DecodeError::BadMagic);
+        }
+        if buf[2] == 0 || buf[2] > PROTOCOL_VERSION {
+            return Err(DecodeError::UnsupportedVersion(buf[2]));
+        }
+
+        let length = u16::from_le_bytes([buf[3], buf[4]]) as usize;
+        let checksum_start = HEADER_SIZE + length;
+        if buf.len() < checksum_start + CHECKSUM_SIZE {
+            return Err(DecodeError::ShortBuffer);
+        }
+
+        let checksum_b: [u8; 4] = buf[checksum_start..checksum_start + CHECKSUM_SIZE]
+            .try_into()
+            .map_err(|_| DecodeError::ShortBuffer)?;
+        if crc32(&buf[..checksum_start]) != u32::from_le_bytes(checksum_b) {
+            return Err(DecodeError::BadChecksum);
+        }
+
+        if length < PAYLOAD_V1_SIZE {
+            return Err(DecodeError::ShortBuffer);
+        }
+        let payload = &buf[HEADER_SIZE..checksum_start];
+
+        let state = byte_to_state(payload[0])?;
+        // Version 1 frames do not carry the phase, a request belongs to the phase of its state
+        let phase = match payload.get(PAYLOAD_V2_SIZE - 1) {
+            Some(byte) => byte_to_state(*byte)?,
+            None => state,
         };
 
         let mut transaction_id_b: [u8; 4] = [0; 4];
-        transaction_id_b.clone_from_slice(&buf[1..5]);
+        transaction_id_b.clone_from_slice(&payload[1..5]);
 
         let mut amount_b: [u8; 4] = [0; 4];
-        amount_b.clone_from_slice(&buf[5..9]);
+        amount_b.clone_from_slice(&payload[5..9]);
 
         let mut service_b: [u8; 4] = [0; 4];
-        service_b.clone_from_slice(&buf[9..13]);
+        service_b.clone_from_slice(&payload[9..13]);
 
-        Transaction {
+        // Frames older than version 3 do not carry the reference
+        let (reference, reference_end) = match payload.get(PAYLOAD_V2_SIZE..PAYLOAD_V2_SIZE + 2) {
+            Some(length_b) => {
+                let start = PAYLOAD_V2_SIZE + 2;
+                let end = start + u16::from_le_bytes([length_b[0], length_b[1]]) as usize;
+                let reference_b = payload.get(start..end).ok_or(DecodeError::ShortBuffer)?;
+                (String::from_utf8_lossy(reference_b).to_string(), end)
+            }
+            None => (String::new(), PAYLOAD_V2_SIZE),
+        };
+
+        // Frames older than version 4 only carry whole major units of the default currency,
+        // a 32 bits amount always fits in its minor units
+        let amount = match payload.get(reference_end..reference_end + MONEY_SIZE) {
+            Some(money_b) => {
+                let mut minor_units_b: [u8; 8] = [0; 8];
+                minor_units_b.clone_from_slice(&money_b[0..8]);
+                let currency = std::str::from_utf8(&money_b[8..MONEY_SIZE])
+                    .ok()
+                    .and_then(|code| Currency::new(code).ok())
+                    .ok_or(DecodeError::BadCurrency)?;
+                Money::new(i64::from_le_bytes(minor_units_b), currency)
+            }
+            None => {
+                let currency = Currency::default();
+                let major_units = i32::from_le_bytes(amount_b) as i64;
+                Money::new(major_units * 10_i64.pow(currency.minor_digits()), currency)
+            }
+        };
+
+        // Frames older than version 5 were sent before epochs existed, they belong to epoch 0
+        let epoch_start = reference_end + MONEY_SIZE;
+        let epoch = match payload.get(epoch_start..epoch_start + EPOCH_SIZE) {
+            Some(epoch_b) => {
+                let mut epoch_le: [u8; 8] = [0; 8];
+                epoch_le.clone_from_slice(epoch_b);
+                u64::from_le_bytes(epoch_le)
+            }
+            None => 0,
+        };
+
+        Ok(Transaction {
             transaction_id: i32::from_le_bytes(transaction_id_b),
-            amount: i32::from_le_bytes(amount_b),
+            amount,
             transaction_state: state,
             service: i32::from_le_bytes(service_b),
+            phase,
+            reference,
+            epoch,
+        })
+    }
+}
+
+/// Converts a state into the byte that represents it in a frame
+fn state_to_byte(state: TransactionState) -> u8 {
+    match state {
+        TransactionState::Prepare => b'P',
+        TransactionState::Abort => b'A',
+        TransactionState::Commit => b'C',
+        TransactionState::Query => b'Q',
+        TransactionState::Wait => b'W',
+        _ => {
+            panic!("Unrecognized TransactionState")
         }
     }
 }
+
+/// Converts the byte that represents a state in a frame into the state
+fn byte_to_state(byte: u8) -> Result<TransactionState, DecodeError> {
+    match byte {
+        b'P' => Ok(TransactionState::Prepare),
+        b'C' => Ok(TransactionState::Commit),
+        b'A' => Ok(TransactionState::Abort),
+        b'Q' => Ok(TransactionState::Query),
+        b'W' => Ok(TransactionState::Wait),
+        other => Err(DecodeError::BadState(other)),
+    }
+}
+
+/// Computes the CRC-32 (IEEE) checksum of the given bytes
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFF_u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transaction(reference: &str) -> Transaction {
+        Transaction {
+            transaction_state: TransactionState::Prepare,
+            transaction_id: 42,
+            amount: Money::new(150025, Currency::new("USD").unwrap()),
+            service: 2,
+            phase: TransactionState::Commit,
+            reference: reference.to_string(),
+            epoch: 7,
+        }
+    }
+
+    /// Builds a frame of the given version around the payload
+    fn build_frame(version: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = MAGIC.to_vec();
+        frame.push(version);
+        frame.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+        frame.extend_from_slice(payload);
+        reseal(frame)
+    }
+
+    /// Replaces the checksum of the frame whit the one of its current content
+    fn reseal(mut frame: Vec<u8>) -> Vec<u8> {
+        let checksum_start = HEADER_SIZE + u16::from_le_bytes([frame[3], frame[4]]) as usize;
+        frame.truncate(checksum_start);
+        let checksum = crc32(&frame);
+        frame.extend_from_slice(&checksum.to_le_bytes());
+        frame
+    }
+
+    #[test]
+    fn decodes_what_it_serializes() {
+        let frame = transaction("RES-1/hotel").serialize();
+        let decoded = Transaction::try_from(&frame[..]).unwrap();
+        assert_eq!(decoded.transaction_state, TransactionState::Prepare);
+        assert_eq!(decoded.transaction_id, 42);
+        assert_eq!(
+            decoded.amount,
+            Money::new(150025, Currency::new("USD").unwrap())
+        );
+        assert_eq!(decoded.service, 2);
+        assert_eq!(decoded.phase, TransactionState::Commit);
+        assert_eq!(decoded.reference, "RES-1/hotel");
+        assert_eq!(decoded.epoch, 7);
+    }
+
+    #[test]
+    fn rejects_truncated_frames() {
+        let frame = transaction("RES-1").serialize();
+        for size in 0..frame.len() {
+            assert_eq!(
+                Transaction::try_from(&frame[..size]).err(),
+                Some(DecodeError::ShortBuffer),
+                "{}",
+                size
+            );
+        }
+        let short_payload = build_frame(1, &[b'C'; PAYLOAD_V1_SIZE - 1]);
+        assert_eq!(
+            Transaction::try_from(&short_payload[..]).err(),
+            Some(DecodeError::ShortBuffer)
+        );
+    }
+
+    #[test]
+    fn rejects_a_bad_magic_number() {
+        let mut frame = transaction("RES-1").serialize();
+        frame[0] = b'X';
+        assert_eq!(
+            Transaction::try_from(&frame[..]).err(),
+            Some(DecodeError::BadMagic)
+        );
+    }
+
+    #[test]
+    fn rejects_a_bad_checksum() {
+        let mut frame = transaction("RES-1").serialize();
+        frame[HEADER_SIZE + 1] ^= 0xFF;
+        assert_eq!(
+            Transaction::try_from(&frame[..]).err(),
+            Some(DecodeError::BadChecksum)
+        );
+
+        let mut frame = transaction("RES-1").serialize();
+        let last = frame.len() - 1;
+        frame[last] ^= 0xFF;
+        assert_eq!(
+            Transaction::try_from(&frame[..]).err(),
+            Some(DecodeError::BadChecksum)
+        );
+    }
+
+    #[test]
+    fn rejects_an_unknown_state() {
+        let mut frame = transaction("RES-1").serialize();
+        frame[HEADER_SIZE] = b'Z';
+        assert_eq!(
+            Transaction::try_from(&reseal(frame)[..]).err(),
+            Some(DecodeError::BadState(b'Z'))
+        );
+
+        let mut frame = transaction("RES-1").serialize();
+        frame[HEADER_SIZE + PAYLOAD_V2_SIZE - 1] = b'Z';
+        assert_eq!(
+            Transaction::try_from(&reseal(frame)[..]).err(),
+            Some(DecodeError::BadState(b'Z'))
+        );
+    }
+
+    #[test]
+    fn rejects_unsupported_versions() {
+        for version in [0, PROTOCOL_VERSION + 1] {
+            let mut frame = transaction("RES-1").serialize();
+            frame[2] = version;
+            assert_eq!(
+                Transaction::try_from(&reseal(frame)[..]).err(),
+                Some(DecodeError::UnsupportedVersion(version))
+            );
+        }
+    }
+
+    #[test]
+    fn decodes_a_version_1_frame() {
+        let mut payload = vec![b'C'];
+        payload.extend_from_slice(&42_i32.to_le_bytes());
+        payload.extend_from_slice(&1500_i32.to_le_bytes());
+        payload.extend_from_slice(&2_i32.to_le_bytes());
+        let frame = build_frame(1, &payload);
+
+        let decoded = Transaction::try_from(&frame[..]).unwrap();
+        let currency = Currency::default();
+        assert_eq!(decoded.transaction_state, TransactionState::Commit);
+        assert_eq!(decoded.transaction_id, 42);
+        assert_eq!(
+            decoded.amount,
+            Money::new(1500 * 10_i64.pow(currency.minor_digits()), currency)
+        );
+        assert_eq!(decoded.service, 2);
+        assert_eq!(decoded.phase, TransactionState::Commit);
+        assert_eq!(decoded.reference, "");
+        assert_eq!(decoded.epoch, 0);
+    }
+
+    #[test]
+    fn truncates_a_multibyte_reference_at_a_char_boundary() {
+        // Every 'ñ' takes two bytes, so MAX_REFERENCE_SIZE falls in the middle of one
+        let reference = format!("a{}", "ñ".repeat(MAX_REFERENCE_SIZE));
+        let frame = transaction(&reference).serialize();
+        assert!(frame.len() <= MAX_FRAME_SIZE);
+
+        let decoded = Transaction::try_from(&frame[..]).unwrap();
+        assert_eq!(
+            decoded.reference,
+            format!("a{}", "ñ".repeat((MAX_REFERENCE_SIZE - 1) / 2))
+        );
+        assert_eq!(decoded.epoch, 7);
+    }
+}
@@ -1,190 +1,769 @@
-use crate::helper::id_to_microservice;
-use crate::transaction::{Transaction, TransactionState};
-use std::collections::HashMap;
-use std::net::UdpSocket;
+use crate::config::Config;
+use crate::decision_log::{Decision, DecisionLog};
+use crate::participant::ParticipantRegistry;
+use crate::replication::Replicator;
+use crate::transaction::{Transaction, TransactionState, MAX_FRAME_SIZE};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
 use std::sync::{Arc, Condvar, Mutex};
-use std::thread;
-use std::time::Duration;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
-use crate::payment::Payment;
+use crate::reservation::Reservation;
+use crate::transaction_outcome::TransactionOutcome;
 
-/// The address of the COORDINATOR_ADDR minus the last digit which will be the id of the alGlobo instance
-const TRANSACTION_COORDINATOR_ADDR: &str = "127.0.0.1:123";
-/// The amount of stakeholders
-const STAKEHOLDERS: usize = 3;
-/// Time before decide that microservice is down
-const TIMEOUT: Duration = Duration::from_secs(5);
+/// The default amount of transactions that can be processed at the same time
+pub const MAX_IN_FLIGHT: usize = 8;
+/// How often the responder checks if the coordinator was shut down
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
 
-/// Struct that represents the transaction logic of the alGlobo leader
+/// How the coordinator sends again a message to the microservices that did not respond
+#[derive(Copy, Clone)]
+pub struct RetryPolicy {
+    /// Amount of times a message is sent before deciding that the microservice is down
+    pub max_attempts: u32,
+    /// Time waited for the responses after the first attempt
+    pub initial_backoff: Duration,
+    /// Factor applied to the waiting time after each attempt
+    pub backoff_multiplier: u32,
+    /// The longest time waited between two attempts when a decision is sent until acknowledged
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 4,
+            initial_backoff: Duration::from_millis(500),
+            backoff_multiplier: 2,
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+/// The response of each service taking part in a phase, it's None while the service did not respond
+type Votes = BTreeMap<usize, Option<TransactionState>>;
+/// The responses of each service, by transaction id and phase of the protocol
+type Responses = HashMap<(i32, TransactionState), Votes>;
+/// A transaction queued through submit_async and where to send its result
+type Job = (i32, Reservation, Sender<TransactionOutcome>);
+
+/// Handle to a transaction queued through submit_async, it can be polled for the outcome
+pub struct SubmitHandle {
+    transaction_id: i32,
+    receiver: Receiver<TransactionOutcome>,
+    result: Option<TransactionOutcome>,
+}
+
+impl SubmitHandle {
+    /// Returns the id of the transaction the handle belongs to
+    pub fn transaction_id(&self) -> i32 {
+        self.transaction_id
+    }
+
+    /// Returns the outcome of the transaction if it already finished, without blocking
+    pub fn poll(&mut self) -> Option<TransactionOutcome> {
+        if self.result.is_none() {
+            self.result = match self.receiver.try_recv() {
+                Ok(outcome) => Some(outcome),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => Some(TransactionOutcome::InDoubt),
+            };
+        }
+        self.result.clone()
+    }
+
+    /// Blocks until the transaction finishes and returns its outcome, it's InDoubt if the
+    /// coordinator stopped before finishing it
+    pub fn wait(mut self) -> TransactionOutcome {
+        match self.result.take() {
+            Some(outcome) => outcome,
+            None => self.receiver.recv().unwrap_or(TransactionOutcome::InDoubt),
+        }
+    }
+}
+
+/// Struct that represents the transaction logic of the alGlobo leader. It can be cloned to
+/// submit transactions from several threads, up to max_in_flight of them are processed at once
 pub struct TransactionCoordinator {
     id: usize,
-    log: HashMap<i32, TransactionState>,
+    log: Arc<Mutex<DecisionLog>>,
+    registry: Arc<ParticipantRegistry>,
     socket: UdpSocket,
-    responses: Arc<(Mutex<Vec<Option<TransactionState>>>, Condvar)>,
+    responses: Arc<(Mutex<Responses>, Condvar)>,
+    replicator: Option<Arc<dyn Replicator>>,
+    retry_policy: RetryPolicy,
+    in_flight: Arc<(Mutex<usize>, Condvar)>,
+    max_in_flight: usize,
+    jobs: Arc<Mutex<Option<Sender<Job>>>>,
+    epoch: Arc<Mutex<u64>>,
+    resolve_until: Arc<Mutex<Option<i32>>>,
+    stop: Arc<Mutex<bool>>,
+    threads: Arc<Mutex<Vec<JoinHandle<()>>>>,
 }
 
 impl TransactionCoordinator {
-    /// Creates a new alGlobo TransactionCoordinator fo the given id.
-    pub fn new(id: usize) -> TransactionCoordinator {
+    /// Creates a new alGlobo TransactionCoordinator fo the given id, that talks to the microservices of
+    /// the configuration. The decisions logged by a previous run are replayed, the transactions left
+    /// in doubt are only finished once the instance is elected and calls lead.
+    pub fn new(id: usize, config: &Config) -> TransactionCoordinator {
         let coordinator = TransactionCoordinator {
             id,
-            log: HashMap::new(),
-            socket: UdpSocket::bind(format!("{}{}", TRANSACTION_COORDINATOR_ADDR, id))
-                .expect("Error binding socket for transaction coordinator"),
-            responses: Arc::new((Mutex::new(vec![None; STAKEHOLDERS]), Condvar::new())),
+            log: Arc::new(Mutex::new(DecisionLog::open(&format!(
+                "{}{}.log",
+                config.paths.decision_log, id
+            )))),
+            registry: Arc::new(config.registry()),
+            socket: UdpSocket::bind(
+                config
+                    .directory()
+                    .get(id)
+                    .expect("Unknown peer")
+                    .coordinator,
+            )
+            .expect("Error binding socket for transaction coordinator"),
+            responses: Arc::new((Mutex::new(HashMap::new()), Condvar::new())),
+            replicator: None,
+            retry_policy: config.retry_policy(),
+            in_flight: Arc::new((Mutex::new(0), Condvar::new())),
+            max_in_flight: config.max_in_flight,
+            jobs: Arc::new(Mutex::new(None)),
+            epoch: Arc::new(Mutex::new(0)),
+            resolve_until: Arc::new(Mutex::new(None)),
+            stop: Arc::new(Mutex::new(false)),
+            threads: Arc::new(Mutex::new(Vec::new())),
         };
+        coordinator
+            .socket
+            .set_read_timeout(Some(POLL_INTERVAL))
+            .expect("Error setting responder read timeout");
 
         let mut clone = coordinator.clone();
-        thread::spawn(move || clone.responder());
+        coordinator.spawn(move || clone.responder());
 
         coordinator
     }
 
-    /// Receives a transaction id and a payment and communicates with microservices to commit the transaction
-    /// if it's successfully committed return true if it's aborted returns false.
-    pub fn submit(&mut self, t: i32, r: Payment) -> bool {
-        match self.log.get(&t) {
+    /// Stops the background threads and waits for them: the transactions queued through
+    /// submit_async are processed first, and the decisions still being sent in background are
+    /// left in doubt in the log, to be finished by the next leader. The socket of the coordinator is
+    /// closed once every clone is dropped
+    pub fn shutdown(self) {
+        *self.stop.lock().expect("Stop is poisoned") = true;
+        // Dropping the sender makes the workers exit once the queue is empty
+        self.jobs.lock().expect("Jobs is poisoned").take();
+        loop {
+            // The threads joined may spawn finishers, so it's repeated until none is left
+            let threads: Vec<JoinHandle<()>> = self
+                .threads
+                .lock()
+                .expect("Threads is poisoned")
+                .drain(..)
+                .collect();
+            if threads.is_empty() {
+                break;
+            }
+            for handle in threads {
+                if handle.join().is_err() {
+                    println!("[COORDINATOR] a background thread panicked");
+                }
+            }
+        }
+    }
+
+    /// Returns true once the coordinator was shut down
+    fn stopped(&self) -> bool {
+        *self.stop.lock().expect("Stop is poisoned")
+    }
+
+    /// Spawns a background thread that is joined by shutdown. The handles of the threads that
+    /// already finished are dropped, so the finishers do not pile up while the coordinator runs
+    fn spawn<F: FnOnce() + Send + 'static>(&self, f: F) {
+        let mut threads = self.threads.lock().expect("Threads is poisoned");
+        threads.retain(|handle| !handle.is_finished());
+        threads.push(thread::spawn(f));
+    }
+
+    /// Makes the coordinator lead the given election epoch and finishes the transactions the
+    /// previous leaders left in doubt. The epoch is sent whit every message, the microservices
+    /// reject the messages of epochs older than the newest one they saw. Only the elected leader
+    /// calls it, so the followers never run the recovery
+    pub fn lead(&self, epoch: u64) {
+        *self.epoch.lock().expect("Epoch is poisoned") = epoch;
+        self.recover();
+    }
+
+    /// Returns the election epoch sent whit every message
+    pub fn epoch(&self) -> u64 {
+        *self.epoch.lock().expect("Epoch is poisoned")
+    }
+
+    /// Makes the coordinator replicate every decision it records, so the other alGlobo instances
+    /// keep a copy of the decision log
+    pub fn set_replicator(&mut self, replicator: Arc<dyn Replicator>) {
+        self.replicator = Some(replicator);
+    }
+
+    /// Changes how messages are sent again to the microservices that did not respond
+    pub fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.retry_policy = retry_policy;
+    }
+
+    /// Changes the amount of transactions that can be processed at the same time
+    pub fn set_max_in_flight(&mut self, max_in_flight: usize) {
+        self.max_in_flight = max_in_flight;
+    }
+
+    /// Returns the amount of transactions that can be processed at the same time
+    pub fn max_in_flight(&self) -> usize {
+        self.max_in_flight
+    }
+
+    /// Stores a decision received from the leader so it can be used if this instance takes over
+    pub fn apply_replicated(&self, decision: Decision) {
+        self.log.lock().expect("Log is poisoned").record(decision);
+    }
+
+    /// Replicates again the decisions of the log that were not acknowledged by every
+    /// microservice, so the next leader can finish them even if the first replica was lost
+    pub fn replicate_in_doubt(&self) {
+        let replicator = match &self.replicator {
+            Some(replicator) => replicator,
+            None => return,
+        };
+        let in_doubt = self.log.lock().expect("Log is poisoned").in_doubt();
+        for decision in in_doubt {
+            if let Err(e) = replicator.replicate(&decision, self.epoch()) {
+                println!(
+                    "[COORDINATOR] error replicating {}: {}",
+                    decision.transaction_id, e
+                );
+            }
+        }
+    }
+
+    /// Receives a transaction id and a reservation and communicates with microservices to commit the transaction,
+    /// returns the outcome of the transaction. A committed transaction is reported as committed pending
+    /// acknowledgement if some microservice has not acknowledged the commit yet. It may be called
+    /// from several threads at once, if max_in_flight transactions are being processed it waits
+    /// until one of them finishes.
+    pub fn submit(&self, t: i32, r: &Reservation) -> TransactionOutcome {
+        let mut in_flight = self
+            .in_flight
+            .1
+            .wait_while(
+                self.in_flight.0.lock().expect("In flight is poisoned"),
+                |in_flight| *in_flight >= self.max_in_flight,
+            )
+            .expect("In flight is poisoned");
+        *in_flight += 1;
+        drop(in_flight);
+
+        let outcome = self.run_protocol(t, r);
+
+        *self.in_flight.0.lock().expect("In flight is poisoned") -= 1;
+        self.in_flight.1.notify_one();
+
+        outcome
+    }
+
+    /// Queues the transaction to be processed in background and returns immediately a handle to
+    /// get its outcome. The queued transactions are processed by max_in_flight worker threads.
+    pub fn submit_async(&self, t: i32, r: Reservation) -> SubmitHandle {
+        let (sender, receiver) = mpsc::channel();
+
+        let mut jobs = self.jobs.lock().expect("Jobs is poisoned");
+        jobs.get_or_insert_with(|| self.start_workers())
+            .send((t, r, sender))
+            .expect("Error queueing transaction");
+
+        SubmitHandle {
+            transaction_id: t,
+            receiver,
+            result: None,
+        }
+    }
+
+    /// Spawns the threads that process the transactions queued through submit_async and returns
+    /// the channel used to queue them
+    fn start_workers(&self) -> Sender<Job> {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        for _ in 0..self.max_in_flight {
+            let worker = self.clone();
+            let receiver = receiver.clone();
+            self.spawn(move || loop {
+                let job = receiver.lock().expect("Jobs receiver is poisoned").recv();
+                match job {
+                    Ok((t, r, result_sender)) => {
+                        // The handle may have been dropped, nobody is waiting for the outcome then
+                        let _ = result_sender.send(worker.submit(t, &r));
+                    }
+                    Err(_) => break,
+                }
+            });
+        }
+
+        sender
+    }
+
+    /// Runs the part of the protocol the transaction is missing according to the log. After
+    /// a recovery, the transactions up to the last one the previous leaders may have left in flight
+    /// that are not in the log are asked about to the microservices first
+    fn run_protocol(&self, t: i32, r: &Reservation) -> TransactionOutcome {
+        let state = self.log.lock().expect("Log is poisoned").get(t);
+        let resolve_until = *self
+            .resolve_until
+            .lock()
+            .expect("Resolve until is poisoned");
+        match state {
+            None if resolve_until.is_some_and(|until| t <= until) => match self.resolve(t, r) {
+                Some(outcome) => outcome,
+                None => self.full_protocol(t, r),
+            },
             None => self.full_protocol(t, r),
-            Some(TransactionState::Wait) => self.full_protocol(t, r),
+            Some(TransactionState::Wait) => match self.resolve(t, r) {
+                Some(outcome) => outcome,
+                None => self.abort(t, r, TransactionOutcome::AbortedOnRecovery),
+            },
+            Some(TransactionState::Abort) => {
+                self.abort(t, r, TransactionOutcome::AbortedOnRecovery)
+            }
             Some(TransactionState::Commit) => self.commit(t, r),
-            Some(TransactionState::Abort) => self.abort(t, r),
             _ => {
                 panic!("No match for transaction {}", t)
             }
         }
     }
 
+    /// Finishes the transactions whose decision was not acknowledged by every microservice. The
+    /// ones that never got a decision are resolved asking the microservices. The transactions
+    /// submitted next are asked about too up to the last one the previous leaders may have left
+    /// in flight: the last one in the log, plus max_in_flight more whose replication may have
+    /// been lost. It's run by lead, once the epoch of the new leader is set
+    fn recover(&self) {
+        let (in_doubt, last) = {
+            let log = self.log.lock().expect("Log is poisoned");
+            (log.in_doubt(), log.last_transaction_id())
+        };
+        *self
+            .resolve_until
+            .lock()
+            .expect("Resolve until is poisoned") =
+            Some(last.unwrap_or(0).saturating_add(self.max_in_flight as i32));
+        for decision in in_doubt {
+            println!(
+                "[COORDINATOR] recovering {} from the log",
+                decision.transaction_id
+            );
+            let (t, r) = (decision.transaction_id, &decision.reservation);
+            match decision.state {
+                TransactionState::Commit => {
+                    self.commit(t, r);
+                }
+                TransactionState::Wait => {
+                    if self.resolve(t, r).is_none() {
+                        self.abort(t, r, TransactionOutcome::AbortedOnRecovery);
+                    }
+                }
+                _ => {
+                    self.abort(t, r, TransactionOutcome::AbortedOnRecovery);
+                }
+            }
+        }
+    }
+
+    /// Asks the microservices what they know of a transaction the previous leader may have left
+    /// in flight and finishes it accordingly. Following presumed abort, it's only committed if
+    /// any of them committed it, as the previous leader may have aborted it whitout the decision
+    /// being replicated. It's aborted if every one of them answers, or if any of them aborted it
+    /// or never heard of it. If none of them answers, or some of them are prepared and the rest
+    /// do not answer, it's left in doubt for a later retry, as the silent ones may have committed
+    /// it. Returns None only if every one of them answers that it does not know the transaction,
+    /// so it was never prepared
+    fn resolve(&self, t: i32, r: &Reservation) -> Option<TransactionOutcome> {
+        println!("[COORDINATOR] query {}", t);
+        let answers = self.broadcast_and_wait(TransactionState::Query, t, r);
+        let any = |state| answers.values().any(|answer| *answer == Some(state));
+
+        if any(TransactionState::Commit) {
+            return Some(self.commit(t, r));
+        }
+        if answers
+            .values()
+            .all(|answer| *answer == Some(TransactionState::Wait))
+        {
+            println!("[COORDINATOR] {} is unknown, it was never prepared", t);
+            return None;
+        }
+        if any(TransactionState::Abort)
+            || any(TransactionState::Wait)
+            || answers.values().all(Option::is_some)
+        {
+            return Some(self.abort(t, r, TransactionOutcome::AbortedOnRecovery));
+        }
+        println!("[COORDINATOR] {} is left in doubt", t);
+        // The outcome is the same whether the doubt reaches the followers or not
+        let _ = self.record(t, TransactionState::Wait, r);
+        Some(TransactionOutcome::InDoubt)
+    }
+
+    /// Persists the state reached by the transaction and replicates it to the followers. Returns
+    /// an error if the replication failed, the state may then never reach the next leader, so it
+    /// must not be sent to the microservices nor reported
+    fn record(&self, t: i32, state: TransactionState, r: &Reservation) -> Result<(), String> {
+        let decision = Decision {
+            transaction_id: t,
+            state,
+            reservation: r.clone(),
+        };
+        self.log
+            .lock()
+            .expect("Log is poisoned")
+            .record(decision.clone());
+
+        if let Some(replicator) = &self.replicator {
+            if let Err(e) = replicator.replicate(&decision, self.epoch()) {
+                println!("[COORDINATOR] error replicating {}: {}", t, e);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
     /// Is called if the transaction was not preciously logged
-    fn full_protocol(&mut self, t: i32, r: Payment) -> bool {
-        let clone = r;
-        if self.prepare(t, r) {
-            self.commit(t, clone)
-        } else {
-            self.abort(t, clone)
+    fn full_protocol(&self, t: i32, r: &Reservation) -> TransactionOutcome {
+        let votes = match self.prepare(t, r) {
+            Ok(votes) => votes,
+            Err(_) => return TransactionOutcome::InDoubt,
+        };
+
+        if let Some((service, _)) = votes
+            .iter()
+            .find(|(_, vote)| **vote == Some(TransactionState::Abort))
+        {
+            let outcome = TransactionOutcome::AbortedByVote {
+                service: self.registry.name_of(*service),
+            };
+            return self.abort(t, r, outcome);
+        }
+
+        let missing: Vec<String> = votes
+            .iter()
+            .filter(|(_, vote)| vote.is_none())
+            .map(|(service, _)| self.registry.name_of(*service))
+            .collect();
+        if !missing.is_empty() {
+            return self.abort(
+                t,
+                r,
+                TransactionOutcome::AbortedByTimeout { services: missing },
+            );
         }
+
+        self.commit(t, r)
     }
 
-    /// Sends a prepare message and the corresponding transaction info to each  microservice
-    fn prepare(&mut self, t: i32, r: Payment) -> bool {
-        self.log.insert(t, TransactionState::Wait);
+    /// Sends a prepare message and the corresponding transaction info to each  microservice and
+    /// returns the vote of each one, it's None for the ones that did not answer. Nothing is sent
+    /// if the transaction could not be replicated
+    fn prepare(&self, t: i32, r: &Reservation) -> Result<Votes, String> {
+        self.record(t, TransactionState::Wait, r)?;
         println!("[COORDINATOR] prepare {}", t);
-        self.broadcast_and_wait(b'P', t, r, TransactionState::Commit)
+        Ok(self.broadcast_and_wait(TransactionState::Prepare, t, r))
     }
 
-    /// Sends a commit message and the corresponding transaction info to each  microservice
-    fn commit(&mut self, t: i32, r: Payment) -> bool {
-        self.log.insert(t, TransactionState::Commit);
+    /// Sends a commit message and the corresponding transaction info to each  microservice. Once
+    /// the decision is logged the reservation is committed, even if some microservice has not
+    /// acknowledged it yet. It's left in doubt whitout sending it if the decision could not be
+    /// replicated
+    fn commit(&self, t: i32, r: &Reservation) -> TransactionOutcome {
+        if self.record(t, TransactionState::Commit, r).is_err() {
+            return TransactionOutcome::InDoubt;
+        }
         println!("[COORDINATOR] commit {}", t);
-        self.broadcast_and_wait(b'C', t, r, TransactionState::Commit)
+        let pending = self.send_decision(TransactionState::Commit, t, r);
+        if pending.is_empty() {
+            TransactionOutcome::Committed
+        } else {
+            TransactionOutcome::CommittedPendingAck {
+                services: pending
+                    .iter()
+                    .map(|service| self.registry.name_of(*service))
+                    .collect(),
+            }
+        }
     }
 
-    /// Sends an abort message and the corresponding transaction info to each  microservice
-    fn abort(&mut self, t: i32, r: Payment) -> bool {
-        self.log.insert(t, TransactionState::Abort);
+    /// Sends an abort message and the corresponding transaction info to each  microservice and
+    /// returns the given outcome. It's left in doubt whitout sending it if the decision could not
+    /// be replicated
+    fn abort(&self, t: i32, r: &Reservation, outcome: TransactionOutcome) -> TransactionOutcome {
+        if self.record(t, TransactionState::Abort, r).is_err() {
+            return TransactionOutcome::InDoubt;
+        }
         println!("[COORDINATOR] abort {}", t);
-        !self.broadcast_and_wait(b'A', t, r, TransactionState::Abort)
+        self.send_decision(TransactionState::Abort, t, r);
+        outcome
     }
 
-    /// Broadcasts the specified transaction to all microservices, returns true if every microservice
-    /// responded whit the expected state, it returns false in other cases
-    fn broadcast_and_wait(
+    /// Broadcasts the decision to every microservice, the ones that do not acknowledge it are
+    /// left to a background thread that keeps sending it until they do. Returns those microservices.
+    /// A microservice that answers whit another decision already took it, it's reported as a
+    /// conflict and not sent the decision again
+    fn send_decision(&self, state: TransactionState, t: i32, r: &Reservation) -> Vec<usize> {
+        let responses = self.broadcast_and_wait(state, t, r);
+        for (stakeholder, response) in &responses {
+            if let Some(other) = response.filter(|response| *response != state) {
+                self.report_conflict(t, state, *stakeholder, other);
+            }
+        }
+        let pending: Vec<usize> = responses
+            .iter()
+            .filter(|(_, response)| response.is_none())
+            .map(|(stakeholder, _)| *stakeholder)
+            .collect();
+
+        if pending.is_empty() {
+            // If it does not reach the next leader, that one only sends the decision again
+            let _ = self.record(t, TransactionState::Finished, r);
+        } else {
+            println!(
+                "[COORDINATOR] {:?} {} pending acknowledgement of {:?}",
+                state, t, pending
+            );
+            self.finish_in_background(state, t, r.clone(), pending.clone());
+        }
+        pending
+    }
+
+    /// Spawns a thread that sends the decision to the pending microservices until every one of
+    /// them acknowledges it or the coordinator is shut down. It uses its own socket, so its
+    /// responses are not mixed whit the ones of the transactions processed in the meantime
+    fn finish_in_background(
         &self,
-        message: u8,
+        state: TransactionState,
         t: i32,
-        r: Payment,
-        expected: TransactionState,
-    ) -> bool {
-        *self.responses.0.lock().expect("Responses is poisoned") = vec![None; STAKEHOLDERS];
-
-        for stakeholder in 0..STAKEHOLDERS {
-            let amount = match stakeholder {
-                0 => r.bank,
-                1 => r.airline,
-                2 => r.hotel,
-                _ => {
-                    panic!("Unknown stakeholder")
+        r: Reservation,
+        mut pending: Vec<usize>,
+    ) {
+        let coordinator = self.clone();
+        self.spawn(move || {
+            // It's bound to the IP of the coordinator, so it reaches the same microservices
+            let ip = coordinator
+                .socket
+                .local_addr()
+                .expect("Error getting coordinator address")
+                .ip();
+            let socket = UdpSocket::bind((ip, 0)).expect("Error binding finisher socket");
+            let mut backoff = coordinator.retry_policy.initial_backoff;
+            let mut attempt = 1;
+
+            while !pending.is_empty() {
+                if coordinator.stopped() {
+                    println!("[COORDINATOR] {:?} {} left in doubt", state, t);
+                    return;
                 }
-            };
 
-            let state = match message {
-                b'P' => TransactionState::Prepare,
-                b'C' => TransactionState::Commit,
-                b'A' => TransactionState::Abort,
-                _ => {
-                    panic!("Unknown stakeholder")
+                for stakeholder in &pending {
+                    coordinator.send_to_stakeholder(&socket, state, t, &r, *stakeholder, attempt);
                 }
-            };
 
-            let mut msg = Transaction {
-                transaction_id: t,
-                transaction_state: state,
-                service: stakeholder as i32,
-                amount,
-            };
+                let deadline = Instant::now() + backoff;
+                while !pending.is_empty() && !coordinator.stopped() {
+                    let remaining = deadline
+                        .saturating_duration_since(Instant::now())
+                        .min(POLL_INTERVAL);
+                    if remaining == Duration::from_secs(0) {
+                        break;
+                    }
+                    let mut buf = [0; MAX_FRAME_SIZE];
+                    socket
+                        .set_read_timeout(Some(remaining))
+                        .expect("Error setting finisher read timeout");
+                    if let Ok((size, _from)) = socket.recv_from(&mut buf) {
+                        if let Ok(ack) = Transaction::try_from(&buf[..size]) {
+                            if ack.transaction_id == t && ack.phase == state {
+                                // A different decision is final too, it's not sent again
+                                if ack.transaction_state != state {
+                                    coordinator.report_conflict(
+                                        t,
+                                        state,
+                                        ack.service as usize,
+                                        ack.transaction_state,
+                                    );
+                                }
+                                pending.retain(|stakeholder| *stakeholder as i32 != ack.service);
+                            }
+                        }
+                    }
+                }
 
-            println!(
-                "[COORDINATOR] sending {} id {} a {}",
-                message, t, stakeholder
-            );
+                backoff = (backoff * coordinator.retry_policy.backoff_multiplier)
+                    .min(coordinator.retry_policy.max_backoff);
+                attempt += 1;
+            }
 
-            self.socket
-                .send_to(&msg.serialize(), id_to_microservice(stakeholder))
-                .expect("Error sending msg to stakeholder");
-        }
+            println!("[COORDINATOR] {:?} {} acknowledged by everyone", state, t);
+            let _ = coordinator.record(t, TransactionState::Finished, &r);
+        });
+    }
 
-        let responses = self.responses.1.wait_timeout_while(
-            self.responses.0.lock().expect("Responses is poisoned"),
-            TIMEOUT,
-            |responses| responses.iter().any(Option::is_none),
+    /// Reports a microservice that already took another decision for the transaction, which can
+    /// only happen if two leaders decided it differently
+    fn report_conflict(
+        &self,
+        t: i32,
+        state: TransactionState,
+        stakeholder: usize,
+        other: TransactionState,
+    ) {
+        println!(
+            "[COORDINATOR] CONFLICT {} was decided {:?} but {} already holds {:?}",
+            t,
+            state,
+            self.registry.name_of(stakeholder),
+            other
         );
+    }
+
+    /// Broadcasts the specified transaction to the microservices that handle a leg of the reservation and returns the
+    /// response of each one, it's None for the microservices that did not respond. The message is sent again to the
+    /// microservices that did not respond, following the retry policy, before deciding they are down
+    fn broadcast_and_wait(&self, state: TransactionState, t: i32, r: &Reservation) -> Votes {
+        let key = (t, state);
+        let votes: Votes = r.services().into_iter().map(|s| (s, None)).collect();
+        self.responses
+            .0
+            .lock()
+            .expect("Responses is poisoned")
+            .insert(key, votes.clone());
+
+        let mut backoff = self.retry_policy.initial_backoff;
+        for attempt in 1..=self.retry_policy.max_attempts {
+            let pending: Vec<usize> = self.responses.0.lock().expect("Responses is poisoned")[&key]
+                .iter()
+                .filter(|(_, response)| response.is_none())
+                .map(|(stakeholder, _)| *stakeholder)
+                .collect();
 
-        return match responses {
-            Ok(wait_result) => {
-                if wait_result.1.timed_out() {
-                    println!("[COORDINATOR] timeout {}", t);
-                    if expected == TransactionState::Abort {
-                        return true;
+            for stakeholder in pending {
+                self.send_to_stakeholder(&self.socket, state, t, r, stakeholder, attempt);
+            }
+
+            let responses = self.responses.1.wait_timeout_while(
+                self.responses.0.lock().expect("Responses is poisoned"),
+                backoff,
+                |responses| responses[&key].values().any(Option::is_none),
+            );
+
+            match responses {
+                Ok(wait_result) => {
+                    if !wait_result.1.timed_out() {
+                        break;
                     }
-                    false
-                } else {
-                    wait_result
-                        .0
-                        .iter()
-                        .all(|opt| opt.is_some() && (opt.expect("opt is poisoned") == expected))
+                    println!("[COORDINATOR] timeout {} on attempt {}", t, attempt);
+                }
+                Err(e) => {
+                    println!("Error at broadcast_and_wait {}", e);
+                    break;
                 }
             }
-            Err(e) => {
-                println!("Error at broadcast_and_wait {}", e);
-                false
-            }
+
+            backoff *= self.retry_policy.backoff_multiplier;
+        }
+
+        self.responses
+            .0
+            .lock()
+            .expect("Responses is poisoned")
+            .remove(&key)
+            .unwrap_or(votes)
+    }
+
+    /// Sends the specified transaction to a single microservice
+    fn send_to_stakeholder(
+        &self,
+        socket: &UdpSocket,
+        state: TransactionState,
+        t: i32,
+        r: &Reservation,
+        stakeholder: usize,
+        attempt: u32,
+    ) {
+        let participant = self.registry.get(stakeholder).expect("Unknown stakeholder");
+        let leg = r.leg_for(stakeholder).expect("Stakeholder has no leg");
+
+        let mut msg = Transaction {
+            transaction_id: t,
+            transaction_state: state,
+            service: stakeholder as i32,
+            amount: leg.amount,
+            phase: state,
+            reference: leg.reference.clone(),
+            epoch: self.epoch(),
         };
+
+        println!(
+            "[COORDINATOR] sending {:?} id {} a {} (attempt {})",
+            state, t, participant.name, attempt
+        );
+
+        if let Err(e) = socket.send_to(&msg.serialize(), &participant.address) {
+            println!("[COORDINATOR] error sending to {}: {}", participant.name, e);
+        }
     }
 
-    /// Receives the responses form the microservices and stores it in responses
+    /// Receives the responses form the microservices and stores it in responses, until the
+    /// coordinator is shut down
     fn responder(&mut self) {
-        loop {
-            let mut buf = [0; 13];
-            let (size, from) = self
-                .socket
-                .recv_from(&mut buf)
-                .expect("Error receiving message in responder");
+        while !self.stopped() {
+            let mut buf = [0; MAX_FRAME_SIZE];
+            let (size, from) = match self.socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                // The read timeout only gives the chance to check if the coordinator was shut down
+                Err(_) => continue,
+            };
             println!("[COORDINATOR] received {} bytes from {}", size, from);
 
-            let transaction = Transaction::deserialize(buf);
+            let transaction = match Transaction::try_from(&buf[..size]) {
+                Ok(transaction) => transaction,
+                Err(e) => {
+                    println!(
+                        "[COORDINATOR] dropping malformed message from {}: {}",
+                        from, e
+                    );
+                    continue;
+                }
+            };
+
+            if transaction.service < 0 || self.registry.get(transaction.service as usize).is_none()
+            {
+                println!(
+                    "[COORDINATOR] dropping message from unknown service {}",
+                    transaction.service
+                );
+                continue;
+            }
 
             match transaction.transaction_state {
-                TransactionState::Commit => {
-                    println!("[COORDINATOR] received COMMIT from {}", transaction.service);
-                    self.responses.0.lock().expect("Responses is poisoned")
-                        [transaction.service as usize] = Some(TransactionState::Commit);
-                    self.responses.1.notify_all();
-                }
-                TransactionState::Abort => {
-                    println!("[COORDINATOR] received ABORT from {}", transaction.service);
-                    self.responses.0.lock().expect("Responses is poisoned")
-                        [transaction.service as usize] = Some(TransactionState::Abort);
-                    self.responses.1.notify_all();
+                TransactionState::Commit
+                | TransactionState::Abort
+                | TransactionState::Prepare
+                | TransactionState::Wait => {
+                    println!(
+                        "[COORDINATOR] received {:?} for {:?} {} from {}",
+                        transaction.transaction_state,
+                        transaction.phase,
+                        transaction.transaction_id,
+                        transaction.service
+                    );
+                    self.store_response(transaction);
                 }
+                TransactionState::Query => self.answer_query(transaction, from),
                 _ => {
                     println!("[COORDINATOR] ??? {}", transaction.service);
                 }
@@ -192,13 +771,86 @@ impl TransactionCoordinator {
         }
     }
 
-    /// Clones the TransactionCoordinator
+    /// Answers a microservice that asks for the decision of a transaction it prepared whit the
+    /// decision in the log, which may have been replicated by the leader. It's Wait if there is
+    /// no decision yet
+    fn answer_query(&self, query: Transaction, from: SocketAddr) {
+        let state = match self
+            .log
+            .lock()
+            .expect("Log is poisoned")
+            .get(query.transaction_id)
+        {
+            Some(state @ (TransactionState::Commit | TransactionState::Abort)) => state,
+            _ => TransactionState::Wait,
+        };
+        println!(
+            "[COORDINATOR] {} asked for {}, answering {:?}",
+            query.service, query.transaction_id, state
+        );
+        let mut answer = Transaction {
+            transaction_state: state,
+            phase: TransactionState::Query,
+            epoch: self.epoch(),
+            ..query
+        };
+        if let Err(e) = self.socket.send_to(&answer.serialize(), from) {
+            println!("[COORDINATOR] error answering query: {}", e);
+        }
+    }
+
+    /// Stores the response as the answer of the service in the phase of the transaction it
+    /// belongs to. Responses for phases that are not being waited on and duplicated responses are
+    /// discarded, so they can never be counted for another transaction
+    fn store_response(&self, transaction: Transaction) {
+        let mut responses = self.responses.0.lock().expect("Responses is poisoned");
+        match responses.get_mut(&(transaction.transaction_id, transaction.phase)) {
+            Some(votes) => match votes.get_mut(&(transaction.service as usize)) {
+                Some(vote @ None) => {
+                    *vote = Some(transaction.transaction_state);
+                    self.responses.1.notify_all();
+                }
+                Some(Some(_)) => {
+                    println!(
+                        "[COORDINATOR] discarding duplicated response from {}",
+                        transaction.service
+                    );
+                }
+                None => {
+                    println!(
+                        "[COORDINATOR] discarding response from {}, it's not part of {}",
+                        transaction.service, transaction.transaction_id
+                    );
+                }
+            },
+            None => {
+                println!(
+                    "[COORDINATOR] discarding stale response for {}",
+                    transaction.transaction_id
+                );
+            }
+        }
+    }
+}
+
+impl Clone for TransactionCoordinator {
+    /// Clones the TransactionCoordinator, the clone shares the log and the responses whit the original
     fn clone(&self) -> Self {
         TransactionCoordinator {
             id: self.id,
-            log: HashMap::new(),
+            log: self.log.clone(),
+            registry: self.registry.clone(),
             socket: self.socket.try_clone().expect("Error cloning socket"),
             responses: self.responses.clone(),
+            replicator: self.replicator.clone(),
+            retry_policy: self.retry_policy,
+            in_flight: self.in_flight.clone(),
+            max_in_flight: self.max_in_flight,
+            jobs: self.jobs.clone(),
+            epoch: self.epoch.clone(),
+            resolve_until: self.resolve_until.clone(),
+            stop: self.stop.clone(),
+            threads: self.threads.clone(),
         }
     }
 }
@@ -0,0 +1,52 @@
+use std::fmt;
+
+/// This enum represents how the processing of a transaction ended
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum TransactionOutcome {
+    /// Every microservice committed the transaction
+    Committed,
+    /// The transaction was committed but the given services have not acknowledged it yet, the
+    /// commit keeps being sent to them in background
+    CommittedPendingAck { services: Vec<String> },
+    /// The given service voted to abort the transaction
+    AbortedByVote { service: String },
+    /// The given services did not answer the prepare message
+    AbortedByTimeout { services: Vec<String> },
+    /// The transaction was aborted by a previous run of the coordinator, or left undecided by it
+    AbortedOnRecovery,
+    /// The coordinator stopped before reaching a decision that could be reported, the decision
+    /// could not be replicated, or the microservices that answered could not tell the decision
+    /// of the previous leader
+    InDoubt,
+}
+
+impl TransactionOutcome {
+    /// Returns true if the payment was committed, even if it's not acknowledged yet
+    pub fn is_committed(&self) -> bool {
+        matches!(
+            self,
+            TransactionOutcome::Committed | TransactionOutcome::CommittedPendingAck { .. }
+        )
+    }
+}
+
+impl fmt::Display for TransactionOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransactionOutcome::Committed => write!(f, "committed"),
+            TransactionOutcome::CommittedPendingAck { services } => write!(
+                f,
+                "committed pending acknowledgement of {}",
+                services.join(" ")
+            ),
+            TransactionOutcome::AbortedByVote { service } => {
+                write!(f, "aborted by vote of {}", service)
+            }
+            TransactionOutcome::AbortedByTimeout { services } => {
+                write!(f, "aborted by timeout of {}", services.join(" "))
+            }
+            TransactionOutcome::AbortedOnRecovery => write!(f, "aborted on recovery"),
+            TransactionOutcome::InDoubt => write!(f, "in doubt"),
+        }
+    }
+}
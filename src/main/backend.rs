@@ -0,0 +1,221 @@
+use crate::leader_election::{LeaderElectionStrategy, UNKNOWN_LEADER};
+use common::config::Config;
+use common::decision_log::{Decision, DECISION_HEADER};
+use common::replication::{Replicator, UdpReplicator};
+use common::transaction_coordinator::TransactionCoordinator;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The first byte of the messages that carry the last processed line
+const LAST_RECORD_HEADER: u8 = b'L';
+/// The biggest message exchanged between alGlobo instances, a replicated decision may be long
+pub const MAX_MESSAGE_SIZE: usize = 65507;
+
+/// The progress of the leader that is replicated to the other alGlobo instances
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Replicated {
+    /// The last line processed whit every previous line processed
+    LastRecord(usize),
+    /// A decision recorded by the coordinator
+    Decision(Decision),
+}
+
+/// Elects the leader among the alGlobo instances and replicates its progress to the others
+pub trait ClusterBackend {
+    /// Returns true if this instance is the leader and may process the payments
+    fn am_i_leader(&self) -> bool;
+
+    /// Returns the id of the current leader, UNKNOWN_LEADER if it's unknown
+    fn get_leader_id(&self) -> usize;
+
+    /// Returns the epoch of the current leader
+    fn epoch(&self) -> u64;
+
+    /// Replicates the last processed line, it's only called by the leader
+    fn replicate_last_record(&self, last_record: usize);
+
+    /// Returns what the coordinator uses to replicate its decisions
+    fn replicator(&self) -> Arc<dyn Replicator>;
+
+    /// Waits up to the given time for the next progress replicated by the leader
+    fn receive(&mut self, timeout: Duration) -> Option<Replicated>;
+
+    /// Hands the leadership to another instance whitout waiting for the failure to be detected,
+    /// giving it the last processed line and the decisions of the coordinator. It's called by the
+    /// leader once its transactions finished
+    fn step_down(&mut self, last_record: usize, coordinator: &TransactionCoordinator);
+
+    /// Restores the newest epoch seen before a restart, so the next leader is elected for a
+    /// greater one
+    fn restore_epoch(&mut self, epoch: u64);
+
+    /// Stops taking part in the election and waits for the threads of the backend
+    fn stop(&mut self);
+}
+
+/// Leader elected by a LeaderElectionStrategy through the control sockets, whit the progress of
+/// the leader sent through the data socket to every follower, whitout acknowledgements
+pub struct ElectionBackend {
+    id: usize,
+    election: Box<dyn LeaderElectionStrategy>,
+    socket: UdpSocket,
+    peers: Vec<SocketAddr>,
+    buf: Vec<u8>,
+}
+
+impl ElectionBackend {
+    /// Creates the backend of the instance whit the given id, it binds its data socket
+    pub fn new(
+        id: usize,
+        config: &Config,
+        election: Box<dyn LeaderElectionStrategy>,
+    ) -> ElectionBackend {
+        let directory = config.directory();
+        ElectionBackend {
+            id,
+            election,
+            socket: UdpSocket::bind(directory.get(id).expect("Unknown peer").data)
+                .expect("Unable to bind socket in main"),
+            peers: directory.others(id).map(|(_, peer)| peer.data).collect(),
+            buf: vec![0; MAX_MESSAGE_SIZE],
+        }
+    }
+
+    /// Forms the message that tells the other peers the last processed line, it carries the id
+    /// of the sender so the followers can tell who the leader is, and its epoch so they can tell
+    /// if it was deposed
+    fn last_record_msg(&self, last_record: usize) -> Vec<u8> {
+        let mut msg = vec![LAST_RECORD_HEADER];
+        msg.extend_from_slice(&(self.id as u64).to_be_bytes());
+        msg.extend_from_slice(&self.election.epoch().to_be_bytes());
+        msg.extend_from_slice(&(last_record as u64).to_be_bytes());
+        msg
+    }
+}
+
+impl ClusterBackend for ElectionBackend {
+    fn am_i_leader(&self) -> bool {
+        self.election.am_i_leader()
+    }
+
+    fn get_leader_id(&self) -> usize {
+        self.election.get_leader_id()
+    }
+
+    fn epoch(&self) -> u64 {
+        self.election.epoch()
+    }
+
+    fn replicate_last_record(&self, last_record: usize) {
+        for peer in &self.peers {
+            println!("[{}] Sending to peer last record", self.id);
+            self.socket
+                .send_to(&self.last_record_msg(last_record), peer)
+                .expect("Error sending last_record to peers");
+        }
+    }
+
+    fn replicator(&self) -> Arc<dyn Replicator> {
+        Arc::new(UdpReplicator::new(
+            self.id,
+            self.socket
+                .try_clone()
+                .expect("Error cloning socket in main"),
+            self.peers.clone(),
+        ))
+    }
+
+    /// Receives the progress sent by the leader. After a handoff the messages the previous leader
+    /// sent before stepping down are read first, as they are stale once this instance takes over
+    fn receive(&mut self, timeout: Duration) -> Option<Replicated> {
+        let last_record = match self.election.handoff() {
+            Some(last_record) => last_record,
+            None => return self.read(timeout),
+        };
+        if let Some(replicated) = self.read(Duration::from_millis(1)) {
+            return Some(replicated);
+        }
+        println!("[{}] Taking over whit last line {}", self.id, last_record);
+        self.election.take_over();
+        Some(Replicated::LastRecord(last_record))
+    }
+
+    /// Sends the decisions in doubt and the last processed line to every follower, and then the
+    /// handoff to the next leader
+    fn step_down(&mut self, last_record: usize, coordinator: &TransactionCoordinator) {
+        coordinator.replicate_in_doubt();
+        self.replicate_last_record(last_record);
+        if !self.election.step_down(last_record) {
+            println!("[{}] No instance took over the leadership", self.id);
+        }
+    }
+
+    fn restore_epoch(&mut self, epoch: u64) {
+        self.election.restore_epoch(epoch);
+    }
+
+    fn stop(&mut self) {
+        self.election.stop();
+    }
+}
+
+impl ElectionBackend {
+    /// Receives the progress sent by the leader, the messages of deposed leaders are rejected. A
+    /// quiet data socket only means the leader is busy, its failure is detected by the missed
+    /// heartbeats in the election
+    fn read(&mut self, timeout: Duration) -> Option<Replicated> {
+        self.socket
+            .set_read_timeout(Some(timeout.max(Duration::from_millis(1))))
+            .expect("Error setting set_read_timeout in main");
+        let (size, from) = self.socket.recv_from(&mut self.buf).ok()?;
+        let msg = &self.buf[..size];
+
+        let (sender, epoch, replicated) = if msg.first() == Some(&DECISION_HEADER) {
+            match Decision::deserialize(msg) {
+                Some((sender, epoch, decision)) => (sender, epoch, Replicated::Decision(decision)),
+                None => {
+                    println!("[{}] Received a malformed decision", self.id);
+                    return None;
+                }
+            }
+        } else {
+            match parse_last_record_msg(msg) {
+                Some((sender, epoch, last_record)) => {
+                    (sender, epoch, Replicated::LastRecord(last_record))
+                }
+                None => {
+                    println!("[{}] Received a malformed message from {}", self.id, from);
+                    return None;
+                }
+            }
+        };
+
+        if epoch < self.election.epoch() {
+            println!(
+                "[{}] Rejecting message of deposed leader {}, epoch {} is stale",
+                self.id, sender, epoch
+            );
+            return None;
+        }
+        if self.election.get_leader_id() == UNKNOWN_LEADER {
+            println!("[{}] Leader is ({})", self.id, sender);
+            self.election.set_leader(sender);
+        }
+        Some(replicated)
+    }
+}
+
+/// Returns the id of the sender, its epoch and the last processed line of the message, or None
+/// if the message is not a last record message
+fn parse_last_record_msg(msg: &[u8]) -> Option<(usize, u64, usize)> {
+    if msg.len() != 25 || msg[0] != LAST_RECORD_HEADER {
+        return None;
+    }
+    let sender = u64::from_be_bytes(msg[1..9].try_into().ok()?) as usize;
+    let epoch = u64::from_be_bytes(msg[9..17].try_into().ok()?);
+    let last_record = u64::from_be_bytes(msg[17..25].try_into().ok()?) as usize;
+    Some((sender, epoch, last_record))
+}
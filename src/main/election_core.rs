@@ -0,0 +1,303 @@
+use crate::failure_detector::FailureDetector;
+use crate::leader_election::{LeaderElectionStrategy, UNKNOWN_LEADER};
+use common::config::Config;
+use common::peer_directory::PeerDirectory;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// The part of the leader election shared by every strategy: the control socket, the current
+/// leader and its epoch, the failure detector of its heartbeats, the handoff received and the
+/// threads that are joined when the election is stopped
+pub struct ElectionCore {
+    pub id: usize,
+    pub socket: UdpSocket,
+    pub peers: Arc<PeerDirectory>,
+    pub timeout: Duration,
+    detector: Arc<Mutex<FailureDetector>>,
+    leader_id: Arc<(Mutex<Option<usize>>, Condvar)>,
+    stop: Arc<Mutex<bool>>,
+    epoch: Arc<Mutex<u64>>,
+    handoff: Arc<Mutex<Option<usize>>>,
+    threads: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl ElectionCore {
+    /// Creates the core of the election of the given instance, binding its control socket, whit
+    /// the peers and the timeouts of the configuration
+    pub fn new(id: usize, config: &Config) -> ElectionCore {
+        let peers = config.directory();
+        let ret = ElectionCore {
+            id,
+            socket: UdpSocket::bind(peers.get(id).expect("Unknown peer").ctrl)
+                .expect("Unable to bind socket for the leader election"),
+            peers: Arc::new(peers),
+            timeout: config.election_timeout(),
+            detector: Arc::new(Mutex::new(FailureDetector::new(
+                config.heartbeat_interval(),
+                config.timeouts.missed_heartbeats,
+            ))),
+            leader_id: Arc::new((Mutex::new(Some(UNKNOWN_LEADER)), Condvar::new())),
+            stop: Arc::new(Mutex::new(false)),
+            epoch: Arc::new(Mutex::new(0)),
+            handoff: Arc::new(Mutex::new(None)),
+            threads: Arc::new(Mutex::new(Vec::new())),
+        };
+        // The responder wakes up every heartbeat interval to check if the election was stopped
+        ret.socket
+            .set_read_timeout(Some(ret.heartbeat_interval()))
+            .expect("Error setting responder read timeout");
+        ret
+    }
+
+    /// Returns the time between two heartbeats of the leader
+    pub fn heartbeat_interval(&self) -> Duration {
+        self.detector
+            .lock()
+            .expect("detector is poisoned")
+            .interval()
+    }
+
+    /// Spawns a thread that is joined when the election is stopped. The handles of the threads
+    /// that already finished are dropped, so they do not pile up while the instance runs
+    pub fn spawn<F: FnOnce() + Send + 'static>(&self, f: F) {
+        let mut threads = self.threads.lock().expect("threads is poisoned");
+        threads.retain(|handle| !handle.is_finished());
+        threads.push(thread::spawn(f));
+    }
+
+    /// Returns true once the election was stopped
+    pub fn stopped(&self) -> bool {
+        *self.stop.lock().expect("Stop is poisoned")
+    }
+
+    /// Returns the newest epoch seen
+    pub fn epoch(&self) -> u64 {
+        *self.epoch.lock().expect("epoch is poisoned")
+    }
+
+    /// Raises the current epoch if the given one is newer, so the next leader is elected for an
+    /// epoch greater than every epoch seen
+    pub fn observe(&self, epoch: u64) {
+        let mut current = self.epoch.lock().expect("epoch is poisoned");
+        *current = (*current).max(epoch);
+    }
+
+    /// Returns the id of the current leader, waiting while an election is running
+    pub fn get_leader_id(&self) -> usize {
+        self.leader_id
+            .1
+            .wait_while(
+                self.leader_id.0.lock().expect("leader_id is poisoned"),
+                |leader_id| leader_id.is_none(),
+            )
+            .expect("leader_id is poisoned")
+            .expect("Leader is yet none")
+    }
+
+    /// Makes the given instance the leader
+    pub fn set_leader(&self, id: usize) {
+        *self.leader_id.0.lock().expect("leader_id is poisoned") = Some(id);
+        self.leader_id.1.notify_all();
+    }
+
+    /// Marks that an election is running. Returns false if one already is or if the election was
+    /// stopped
+    pub fn start_election(&self) -> bool {
+        if self.stopped() {
+            return false;
+        }
+        let mut leader_id = self.leader_id.0.lock().expect("leader_id is poisoned");
+        if leader_id.is_none() {
+            return false;
+        }
+        *leader_id = None;
+        println!("[{}] Searching for new leader", self.id);
+        true
+    }
+
+    /// Waits up to the given timeout for the election to finish. If it does not, the leader is
+    /// left unknown so a new election starts once the heartbeats are missed again, and it
+    /// returns false
+    pub fn wait_leader(&self, timeout: Duration) -> bool {
+        let (mut leader_id, _) = self
+            .leader_id
+            .1
+            .wait_timeout_while(
+                self.leader_id.0.lock().expect("leader_id is poisoned"),
+                timeout,
+                |leader_id| leader_id.is_none(),
+            )
+            .expect("leader_id is poisoned");
+        if leader_id.is_some() {
+            return true;
+        }
+        *leader_id = Some(UNKNOWN_LEADER);
+        self.leader_id.1.notify_all();
+        false
+    }
+
+    /// Makes this instance the leader of an epoch greater than the given one and every epoch
+    /// seen, and returns it. A stopped instance does not become the leader
+    pub fn claim_leadership(&self, seen_epoch: u64) -> Option<u64> {
+        if self.stopped() {
+            return None;
+        }
+        let epoch = {
+            let mut epoch = self.epoch.lock().expect("epoch is poisoned");
+            *epoch = (*epoch).max(seen_epoch) + 1;
+            *epoch
+        };
+        println!("[{}] Announce coordinator for epoch {}", self.id, epoch);
+        self.set_leader(self.id);
+        self.detector
+            .lock()
+            .expect("detector is poisoned")
+            .heartbeat();
+        Some(epoch)
+    }
+
+    /// Makes the sender the leader if its epoch is newer than the current one, or if it's the
+    /// same epoch and the sender does not have a lower id than the current leader. Returns false
+    /// if the sender is a deposed leader
+    pub fn follow(&self, id_from: usize, epoch: u64) -> bool {
+        let mut current = self.epoch.lock().expect("epoch is poisoned");
+        let mut leader_id = self.leader_id.0.lock().expect("leader_id is poisoned");
+        let accepted = epoch > *current
+            || (epoch == *current
+                && match *leader_id {
+                    Some(leader_id) if leader_id != UNKNOWN_LEADER => id_from >= leader_id,
+                    _ => true,
+                });
+        if !accepted {
+            return false;
+        }
+
+        *current = epoch;
+        if *leader_id != Some(id_from) {
+            println!("[{}] Leader is {} for epoch {}", self.id, id_from, epoch);
+            *leader_id = Some(id_from);
+            self.leader_id.1.notify_all();
+        }
+        self.detector
+            .lock()
+            .expect("detector is poisoned")
+            .heartbeat();
+        true
+    }
+
+    /// Returns the last processed line handed off to this instance, if any
+    pub fn handoff(&self) -> Option<usize> {
+        *self.handoff.lock().expect("handoff is poisoned")
+    }
+
+    /// Keeps the last processed line handed off to this instance until it takes over
+    pub fn set_handoff(&self, handoff: Option<usize>) {
+        *self.handoff.lock().expect("handoff is poisoned") = handoff;
+    }
+
+    /// Every heartbeat interval, sends the heartbeat of the strategy to the other peers if this
+    /// instance is the leader, otherwise makes the strategy find a new leader if the current one
+    /// missed too many heartbeats
+    pub fn heartbeater<S: LeaderElectionStrategy>(
+        &self,
+        strategy: &mut S,
+        heartbeat: impl Fn(&S) -> Vec<u8>,
+    ) {
+        while !self.stopped() {
+            thread::sleep(self.heartbeat_interval());
+            let leader_id = *self.leader_id.0.lock().expect("leader_id is poisoned");
+            let suspected = self
+                .detector
+                .lock()
+                .expect("detector is poisoned")
+                .is_suspected();
+            match leader_id {
+                // An election is running
+                None => {}
+                Some(leader_id) if leader_id == self.id => {
+                    let msg = heartbeat(strategy);
+                    for (_peer_id, peer) in self.peers.others(self.id) {
+                        if let Err(e) = self.socket.send_to(&msg, peer.ctrl) {
+                            println!("[{}] Error sending heartbeat: {}", self.id, e);
+                        }
+                    }
+                }
+                Some(leader_id) if suspected => {
+                    if leader_id == UNKNOWN_LEADER {
+                        println!("[{}] No heartbeats from any leader", self.id);
+                    } else {
+                        println!("[{}] Leader {} missed its heartbeats", self.id, leader_id);
+                    }
+                    strategy.find_new();
+                    self.detector
+                        .lock()
+                        .expect("detector is poisoned")
+                        .heartbeat();
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    /// Waits for a message on the control socket, returns None once the election was stopped
+    pub fn receive(&self, buf: &mut [u8]) -> Option<(usize, SocketAddr)> {
+        while !self.stopped() {
+            // The read timeout only gives the chance to check if the election was stopped
+            if let Ok(received) = self.socket.recv_from(buf) {
+                if !self.stopped() {
+                    return Some(received);
+                }
+            }
+        }
+        None
+    }
+
+    /// Stops the election, calling wake once the stop flag is set so the strategy wakes up its
+    /// own waiters, releases the ones waiting for a leader and joins the threads
+    pub fn stop(&self, wake: impl FnOnce()) {
+        *self.stop.lock().expect("stop is poisoned") = true;
+        wake();
+        {
+            let mut leader_id = self.leader_id.0.lock().expect("leader_id is poisoned");
+            if leader_id.is_none() {
+                *leader_id = Some(UNKNOWN_LEADER);
+            }
+            self.leader_id.1.notify_all();
+        }
+        loop {
+            // A responder joined may have spawned more threads, so it's repeated until none is left
+            let threads: Vec<JoinHandle<()>> = self
+                .threads
+                .lock()
+                .expect("threads is poisoned")
+                .drain(..)
+                .collect();
+            if threads.is_empty() {
+                break;
+            }
+            for handle in threads {
+                if handle.join().is_err() {
+                    println!("[{}] An election thread panicked", self.id);
+                }
+            }
+        }
+    }
+
+    /// clones the ElectionCore
+    pub fn clone(&self) -> ElectionCore {
+        ElectionCore {
+            id: self.id,
+            socket: self.socket.try_clone().expect("Error while cloning socket"),
+            peers: self.peers.clone(),
+            timeout: self.timeout,
+            detector: self.detector.clone(),
+            leader_id: self.leader_id.clone(),
+            stop: self.stop.clone(),
+            epoch: self.epoch.clone(),
+            handoff: self.handoff.clone(),
+            threads: self.threads.clone(),
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use std::time::{Duration, Instant};
+
+/// Decides when the leader is suspected to have fall, by counting the heartbeats it missed
+#[derive(Copy, Clone)]
+pub struct FailureDetector {
+    interval: Duration,
+    allowed_misses: u32,
+    last_heartbeat: Instant,
+}
+
+impl FailureDetector {
+    /// Creates a detector for heartbeats sent every interval, that suspects the leader once more
+    /// than allowed_misses heartbeats in a row were not received
+    pub fn new(interval: Duration, allowed_misses: u32) -> FailureDetector {
+        FailureDetector {
+            interval,
+            allowed_misses,
+            last_heartbeat: Instant::now(),
+        }
+    }
+
+    /// Returns the time between two heartbeats
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Registers a heartbeat of the leader, it's also used to give a new leader a fresh start
+    pub fn heartbeat(&mut self) {
+        self.last_heartbeat = Instant::now();
+    }
+
+    /// Returns true if the leader missed more heartbeats than allowed
+    pub fn is_suspected(&self) -> bool {
+        self.last_heartbeat.elapsed() > self.interval * self.allowed_misses.max(1)
+    }
+}
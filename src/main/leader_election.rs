@@ -1,191 +1,312 @@
-use common::helper::id_to_ctrladdr;
+use crate::election_core::ElectionCore;
+use common::config::Config;
 use std::convert::TryInto;
 use std::mem::size_of;
-use std::net::UdpSocket;
 use std::sync::{Arc, Condvar, Mutex};
-use std::thread;
-use std::time::Duration;
 
-/// The max amount of members
-pub const TEAM_MEMBERS: usize = 5;
-/// The timme before considering that the leader has fall
-pub const TIMEOUT: Duration = Duration::from_secs(20);
+/// The leader id reported while no leader is known
+pub const UNKNOWN_LEADER: usize = usize::MAX;
 
-/// struct used to replace the leader election protocol
+/// An algorithm that elects the leader among the alGlobo instances through their control
+/// sockets. Every leader is elected for a new epoch, greater than any epoch seen before
+pub trait LeaderElectionStrategy {
+    /// Returns true if the instance is the leader
+    fn am_i_leader(&self) -> bool;
+
+    /// Returns the id of the current leader, if it's unknown it returns UNKNOWN_LEADER. It waits
+    /// while an election is running
+    fn get_leader_id(&self) -> usize;
+
+    /// Returns the newest epoch seen, the one of the current leader once it's known
+    fn epoch(&self) -> u64;
+
+    /// Starts search for a new leader
+    fn find_new(&mut self);
+
+    /// Makes the given instance the leader, used when its messages arrive before its announcement
+    fn set_leader(&mut self, id: usize);
+
+    /// Hands the leadership to the instance whit the next highest id that answers, giving it the
+    /// last processed line. Returns false if no instance took it
+    fn step_down(&mut self, last_record: usize) -> bool;
+
+    /// Returns the last processed line handed by a leader that stepped down in favor of this
+    /// instance, which becomes the leader once it calls take_over
+    fn handoff(&self) -> Option<usize>;
+
+    /// Becomes the leader of a new epoch after a handoff, whitout waiting for an election
+    fn take_over(&mut self);
+
+    /// Raises the epoch to the given one if it's newer, so the next leader is elected for a
+    /// greater epoch than the ones seen before a restart
+    fn restore_epoch(&mut self, epoch: u64);
+
+    /// stops leader election protocol and waits for its threads
+    fn stop(&mut self);
+}
+
+/// The size of every message: header, id of the sender and its epoch
+const MSG_SIZE: usize = 1 + size_of::<u64>() * 2;
+/// The size of a handoff, which also carries the last processed line
+const HANDOFF_SIZE: usize = MSG_SIZE + size_of::<u64>();
+
+/// struct used to replace the leader election protocol. Every leader is elected for a new epoch,
+/// greater than any epoch seen before, and the messages of leaders of older epochs are rejected
 pub struct LeaderElection {
-    id: usize,
-    socket: UdpSocket,
-    leader_id: Arc<(Mutex<Option<usize>>, Condvar)>,
+    core: ElectionCore,
     got_ok: Arc<(Mutex<bool>, Condvar)>,
-    stop: Arc<(Mutex<bool>, Condvar)>,
 }
 
 impl LeaderElection {
-    /// Creates a new instance of LeaderElection
-    pub fn new(id: usize) -> LeaderElection {
+    /// Creates a new instance of LeaderElection, whit the peers and the timeout of the configuration
+    pub fn new(id: usize, config: &Config) -> LeaderElection {
         let ret = LeaderElection {
-            id,
-            socket: UdpSocket::bind(id_to_ctrladdr(id))
-                .expect("Unable to bind socket for LeaderElection"),
-            leader_id: Arc::new((Mutex::new(Some(TEAM_MEMBERS)), Condvar::new())),
+            core: ElectionCore::new(id, config),
             got_ok: Arc::new((Mutex::new(false), Condvar::new())),
-            stop: Arc::new((Mutex::new(false), Condvar::new())),
         };
 
+        let clone = ret.clone();
+        ret.core.spawn(move || clone.responder());
         let mut clone = ret.clone();
-        thread::spawn(move || clone.responder());
+        ret.core.spawn(move || {
+            let core = clone.core.clone();
+            core.heartbeater(&mut clone, |me| me.id_to_msg(b'H'))
+        });
 
         ret
     }
 
-    /// Returns true if the instance is the leader
-    pub fn am_i_leader(&self) -> bool {
-        self.get_leader_id() == self.id
-    }
-
-    /// Returns the id of the current leader, if it's unknown it returns TEAM_MEMBERS
-    pub fn get_leader_id(&self) -> usize {
-        self.leader_id
-            .1
-            .wait_while(
-                self.leader_id.0.lock().expect("leader_id is poisoned"),
-                |leader_id| leader_id.is_none(),
-            )
-            .expect("leader_id is poisoned")
-            .expect("Leader is yet none")
-    }
-
-    /// Starts search for a new leader
-    pub fn find_new(&mut self) {
-        if *self.stop.0.lock().expect("Poisoned stop") {
-            return;
-        }
-        if self
-            .leader_id
-            .0
-            .lock()
-            .expect("leader_id is poisoned")
-            .is_none()
-        {
-            return;
-        }
-        println!("[{}] Searching for new leader", self.id);
-        *self.got_ok.0.lock().expect("got_ok is poisoned") = false;
-        *self.leader_id.0.lock().expect("leader_id is poisoned") = None;
-        self.send_election();
-        let got_ok = self.got_ok.1.wait_timeout_while(
-            self.got_ok.0.lock().expect("got_ok is poisoned"),
-            TIMEOUT,
-            |got_it| !*got_it,
-        );
-        if !*got_ok.expect("got_ok is poisoned").0 {
-            self.make_me_leader()
-        } else {
-            let leader_id = self.leader_id.1.wait_while(
-                self.leader_id.0.lock().expect("leader_id is poisoned"),
-                |leader_id| leader_id.is_none(),
-            );
-            match leader_id {
-                Ok(_t) => {}
-                Err(e) => {
-                    panic!("leader lock is poisoned: {}", e)
-                }
-            }
-        }
-    }
-
-    /// Forms a message whit the id of the peer and a byte that represents the message
+    /// Forms a message whit the id of the peer, its epoch and a byte that represents the message
     fn id_to_msg(&self, header: u8) -> Vec<u8> {
         let mut msg = vec![header];
-        msg.extend_from_slice(&self.id.to_le_bytes());
+        msg.extend_from_slice(&(self.core.id as u64).to_le_bytes());
+        msg.extend_from_slice(&self.epoch().to_le_bytes());
         msg
     }
 
     /// Tells other peers to start an election
     fn send_election(&self) {
         let msg = self.id_to_msg(b'E');
-        for peer_id in (self.id + 1)..TEAM_MEMBERS {
-            self.socket
-                .send_to(&msg, id_to_ctrladdr(peer_id))
+        for (_peer_id, peer) in self.core.peers.above(self.core.id) {
+            self.core
+                .socket
+                .send_to(&msg, peer.ctrl)
                 .expect("Error sending election to peer");
         }
     }
 
-    /// Informs the other peers that this instance is the leader
+    /// Informs the other peers that this instance is the leader of a new epoch
     fn make_me_leader(&self) {
-        println!("[{}] Announce coordinator", self.id);
+        if self.core.claim_leadership(self.epoch()).is_none() {
+            return;
+        }
         let msg = self.id_to_msg(b'C');
-        for peer_id in 0..TEAM_MEMBERS {
-            if peer_id != self.id {
-                self.socket
-                    .send_to(&msg, id_to_ctrladdr(peer_id))
-                    .expect("Error sending make_me_leader to peer");
-            }
+        for (_peer_id, peer) in self.core.peers.others(self.core.id) {
+            self.core
+                .socket
+                .send_to(&msg, peer.ctrl)
+                .expect("Error sending make_me_leader to peer");
         }
-        *self.leader_id.0.lock().expect("Poisoned leader_id") = Some(self.id);
-        self.leader_id.1.notify_all();
     }
 
     /// Receives the responses form the peer and responds accordingly, if receives ok makes got_ok
-    /// true and notify_all, if received Election spawns a tread for find_new, and if it receives
-    /// coordinator makes the received peer the leader
-    fn responder(&mut self) {
-        while !*self.stop.0.lock().expect("Stop is poisoned") {
-            let mut buf = [0; size_of::<usize>() + 1];
-            let (_size, _from) = self
-                .socket
-                .recv_from(&mut buf)
-                .expect("responder found an error at recv_from");
-            let id_from = usize::from_le_bytes(buf[1..].try_into().expect("Error getting id_from"));
-            if *self.stop.0.lock().expect("Stop is poisoned") {
-                break;
+    /// true and notify_all, if received Election spawns a tread for find_new, if it receives
+    /// coordinator makes the received peer the leader, if it receives a heartbeat of the
+    /// leader registers it in the failure detector and if it receives a handoff answers ok and
+    /// keeps the last processed line until it takes over. Coordinators, heartbeats and handoffs
+    /// of deposed leaders are rejected
+    fn responder(&self) {
+        let mut buf = [0; HANDOFF_SIZE];
+        while let Some((size, _from)) = self.core.receive(&mut buf) {
+            if size != MSG_SIZE && !(size == HANDOFF_SIZE && buf[0] == b'S') {
+                println!("[{}] Received a malformed message", self.core.id);
+                continue;
             }
+            let id_from =
+                u64::from_le_bytes(buf[1..9].try_into().expect("Error getting id_from")) as usize;
+            let epoch = u64::from_le_bytes(buf[9..17].try_into().expect("Error getting epoch"));
             match &buf[0] {
                 b'O' => {
-                    println!("[{}] Received OK from {}", self.id, id_from);
+                    println!("[{}] Received OK from {}", self.core.id, id_from);
+                    self.core.observe(epoch);
                     *self.got_ok.0.lock().expect("got_ok is poisoned") = true;
                     self.got_ok.1.notify_all();
                 }
                 b'E' => {
-                    println!("[{}] Received election from {}", self.id, id_from);
-                    if id_from < self.id {
-                        self.socket
-                            .send_to(&self.id_to_msg(b'O'), id_to_ctrladdr(id_from))
+                    println!("[{}] Received election from {}", self.core.id, id_from);
+                    self.core.observe(epoch);
+                    if id_from < self.core.id {
+                        let peer = match self.core.peers.get(id_from) {
+                            Some(peer) => peer,
+                            None => {
+                                println!("[{}] Unknown peer {}", self.core.id, id_from);
+                                continue;
+                            }
+                        };
+                        self.core
+                            .socket
+                            .send_to(&self.id_to_msg(b'O'), peer.ctrl)
                             .expect("Error sending ok");
                         let mut me = self.clone();
-                        thread::spawn(move || me.find_new());
+                        self.core.spawn(move || me.find_new());
                     }
                 }
                 b'C' => {
-                    println!("[{}] Received new coordinator {}", self.id, id_from);
-                    *self.leader_id.0.lock().expect("leader_id is poisoned") = Some(id_from);
-                    self.leader_id.1.notify_all();
+                    println!(
+                        "[{}] Received new coordinator {} for epoch {}",
+                        self.core.id, id_from, epoch
+                    );
+                    if !self.core.follow(id_from, epoch) {
+                        println!(
+                            "[{}] Rejecting coordinator {}, epoch {} is stale",
+                            self.core.id, id_from, epoch
+                        );
+                    }
+                }
+                b'H' => {
+                    if !self.core.follow(id_from, epoch) {
+                        println!(
+                            "[{}] Rejecting heartbeat of deposed leader {}, epoch {} is stale",
+                            self.core.id, id_from, epoch
+                        );
+                    }
+                }
+                b'S' => {
+                    let last_record = u64::from_le_bytes(
+                        buf[17..].try_into().expect("Error getting last_record"),
+                    ) as usize;
+                    if epoch < self.epoch() {
+                        println!(
+                            "[{}] Rejecting handoff of deposed leader {}, epoch {} is stale",
+                            self.core.id, id_from, epoch
+                        );
+                        continue;
+                    }
+                    println!(
+                        "[{}] Leader {} handed off the leadership whit last line {}",
+                        self.core.id, id_from, last_record
+                    );
+                    self.core.set_handoff(Some(last_record));
+                    if let Some(peer) = self.core.peers.get(id_from) {
+                        self.core
+                            .socket
+                            .send_to(&self.id_to_msg(b'O'), peer.ctrl)
+                            .expect("Error sending ok");
+                    }
                 }
                 _ => {
-                    println!("[{}] Unknown message from {}", self.id, id_from);
+                    println!("[{}] Unknown message from {}", self.core.id, id_from);
                 }
             }
         }
-        *self.stop.0.lock().expect("stop is poisoned") = false;
-    }
-
-    /// stops leader election protocol
-    pub fn stop(&mut self) {
-        *self.stop.0.lock().expect("stop is poisoned") = true;
     }
 
     /// clones the LeaderElection
     fn clone(&self) -> LeaderElection {
         LeaderElection {
-            id: self.id,
-            socket: self.socket.try_clone().expect("Error while cloning socket"),
-            leader_id: self.leader_id.clone(),
+            core: self.core.clone(),
             got_ok: self.got_ok.clone(),
-            stop: self.stop.clone(),
         }
     }
+}
+
+impl LeaderElectionStrategy for LeaderElection {
+    fn am_i_leader(&self) -> bool {
+        self.get_leader_id() == self.core.id
+    }
+
+    fn get_leader_id(&self) -> usize {
+        self.core.get_leader_id()
+    }
+
+    fn epoch(&self) -> u64 {
+        self.core.epoch()
+    }
+
+    /// Sends an election to the peers whit a greater id, and becomes the leader if none of them
+    /// answers before the timeout, unless the election is stopped in the meantime
+    fn find_new(&mut self) {
+        if !self.core.start_election() {
+            return;
+        }
+        *self.got_ok.0.lock().expect("got_ok is poisoned") = false;
+        self.send_election();
+        let got_ok = self.got_ok.1.wait_timeout_while(
+            self.got_ok.0.lock().expect("got_ok is poisoned"),
+            self.core.timeout,
+            |got_it| !*got_it && !self.core.stopped(),
+        );
+        if self.core.stopped() {
+            return;
+        }
+        if !*got_ok.expect("got_ok is poisoned").0 {
+            self.make_me_leader()
+        } else {
+            // Waits for the coordinator of the instances that answered
+            self.core.get_leader_id();
+        }
+    }
+
+    fn set_leader(&mut self, id: usize) {
+        self.core.set_leader(id);
+    }
+
+    /// Sends the handoff to the other peers from the highest id down, until one answers ok
+    fn step_down(&mut self, last_record: usize) -> bool {
+        let mut msg = self.id_to_msg(b'S');
+        msg.extend_from_slice(&(last_record as u64).to_le_bytes());
+        let mut peer_ids: Vec<usize> = self
+            .core
+            .peers
+            .others(self.core.id)
+            .map(|(id, _)| id)
+            .collect();
+        peer_ids.reverse();
+        for peer_id in peer_ids {
+            println!(
+                "[{}] Handing off the leadership to {}",
+                self.core.id, peer_id
+            );
+            *self.got_ok.0.lock().expect("got_ok is poisoned") = false;
+            let peer = self.core.peers.get(peer_id).expect("Unknown peer");
+            if let Err(e) = self.core.socket.send_to(&msg, peer.ctrl) {
+                println!("[{}] Error sending handoff: {}", self.core.id, e);
+                continue;
+            }
+            let got_ok = self.got_ok.1.wait_timeout_while(
+                self.got_ok.0.lock().expect("got_ok is poisoned"),
+                self.core.heartbeat_interval(),
+                |got_it| !*got_it && !self.core.stopped(),
+            );
+            if *got_ok.expect("got_ok is poisoned").0 {
+                return true;
+            }
+            if self.core.stopped() {
+                return false;
+            }
+        }
+        false
+    }
+
+    fn handoff(&self) -> Option<usize> {
+        self.core.handoff()
+    }
+
+    fn take_over(&mut self) {
+        self.core.set_handoff(None);
+        self.make_me_leader();
+    }
+
+    fn restore_epoch(&mut self, epoch: u64) {
+        self.core.observe(epoch);
+    }
 
-    pub fn set_leader(&mut self, id: usize) {
-        *self.leader_id.0.lock().expect("leader_id is poisoned") = Some(id);
+    /// Stops the threads, waking up the ones waiting for a leader or for an ok, and joins them
+    fn stop(&mut self) {
+        self.core.stop(|| {
+            // Notified whit the lock held, so a waiter checks the stop flag or gets woken up
+            let _got_ok = self.got_ok.0.lock().expect("got_ok is poisoned");
+            self.got_ok.1.notify_all();
+        });
     }
 }
@@ -1,152 +1,335 @@
+mod backend;
+mod election_core;
+mod failure_detector;
 mod leader_election;
+mod raft;
+mod ring_election;
 
 use structopt::StructOpt;
 
-use crate::leader_election::{LeaderElection, TEAM_MEMBERS, TIMEOUT};
-use common::helper::id_to_dataaddr;
+use crate::backend::{ClusterBackend, ElectionBackend, Replicated};
+use crate::leader_election::LeaderElection;
+use crate::raft::RaftBackend;
+use crate::ring_election::RingElection;
+use common::checkpoint::Checkpoint;
+use common::config::{Config, Election};
 use common::transaction_coordinator::TransactionCoordinator;
+use common::transaction_outcome::TransactionOutcome;
+use std::collections::{BTreeSet, VecDeque};
+use std::fs;
 use std::fs::File;
-use std::io::Write;
-use std::net::UdpSocket;
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
 use std::time::Duration;
-use std::{fs, thread};
 
-use common::payment::Payment;
+use common::reservation::{read_reservations, PaymentEntry, Reservation};
 
 /// Receives the id of the new AlGlobo instance.
 #[derive(StructOpt)]
 struct Cli {
     /// The new worker id (Type u32).
     id: usize,
+    /// The cluster configuration file, a TOML or a JSON file.
+    #[structopt(long)]
+    config: Option<String>,
+    /// The file whit the reservations to process, a CSV or a JSON array.
+    #[structopt(long)]
+    payments: Option<String>,
+    /// The file where the failed transactions are written.
+    #[structopt(long)]
+    failed_transactions: Option<String>,
+    /// The milliseconds waited during an election.
+    #[structopt(long)]
+    election_timeout: Option<u64>,
+    /// The milliseconds between two heartbeats of the leader.
+    #[structopt(long)]
+    heartbeat_interval: Option<u64>,
+    /// The heartbeats in a row that can be missed before starting an election.
+    #[structopt(long)]
+    missed_heartbeats: Option<u32>,
+    /// The amount of transactions processed at the same time.
+    #[structopt(long)]
+    max_in_flight: Option<usize>,
+    /// The algorithm used to elect the leader, bully, ring or raft.
+    #[structopt(long)]
+    election: Option<Election>,
+}
+
+/// Loads the configuration and applies the overrides of the command line
+fn load_config(args: &Cli) -> Result<Config, String> {
+    let mut config = Config::load_or_default(args.config.as_deref())?;
+    if let Some(payments) = &args.payments {
+        config.paths.payments = payments.clone();
+    }
+    if let Some(failed_transactions) = &args.failed_transactions {
+        config.paths.failed_transactions = failed_transactions.clone();
+    }
+    if let Some(election_timeout) = args.election_timeout {
+        config.timeouts.election_ms = election_timeout;
+    }
+    if let Some(heartbeat_interval) = args.heartbeat_interval {
+        config.timeouts.heartbeat_interval_ms = heartbeat_interval;
+    }
+    if let Some(missed_heartbeats) = args.missed_heartbeats {
+        config.timeouts.missed_heartbeats = missed_heartbeats;
+    }
+    if let Some(max_in_flight) = args.max_in_flight {
+        config.max_in_flight = max_in_flight;
+    }
+    if let Some(election) = args.election {
+        config.election = election;
+    }
+    config.validate()?;
+    config.check_peer(args.id)?;
+    Ok(config)
 }
 
 /// AlGlobo instance main loop
 fn main() {
     let args = Cli::from_args();
     let id = args.id;
+    let config = match load_config(&args) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("[{}] Invalid configuration: {}", id, e);
+            return;
+        }
+    };
     println!("[{}] Start", id);
 
-    let socket = UdpSocket::bind(id_to_dataaddr(id)).expect("Unable to bind socket in main");
-    let csv = fs::read_to_string("./resources/payments.csv")
+    let registry = config.registry();
+    let reservations = read_reservations(&config.paths.payments, &registry)
         .expect("Something went wrong reading the file");
-    let lines = csv.split('\n').count() - 1;
-    let reader = csv::Reader::from_reader(csv.as_bytes());
-    let mut iter = reader.into_deserialize();
-    let mut scrum_master = LeaderElection::new(id);
-    let mut buf = [0; 8];
-    let mut last_record: usize = 0;
+    // The lines of the payments file, the line numbers missing from it are never waited for
+    let input_lines: BTreeSet<usize> = reservations.iter().filter_map(|(line, _)| *line).collect();
+    let lines = input_lines.iter().next_back().copied().unwrap_or(0);
+    let mut iter = reservations.into_iter();
+    let mut backend = new_backend(id, &config);
+    let mut checkpoint = Checkpoint::open(&config.paths.checkpoint, id);
+    let mut last_record = checkpoint.last_record();
+    backend.restore_epoch(checkpoint.epoch());
+    println!(
+        "[{}] Restored last line {} and epoch {}",
+        id,
+        last_record,
+        checkpoint.epoch()
+    );
     let mut failed_transactions_file =
-        get_failed_transactions_file("src/main/failed_transactions.csv");
-    let mut coordinator = TransactionCoordinator::new(id);
+        get_failed_transactions_file(&config.paths.failed_transactions);
+    let mut coordinator = TransactionCoordinator::new(id, &config);
+    let mut in_flight = VecDeque::new();
+    let mut reached_eof = false;
+    let mut finished_lines = BTreeSet::new();
+    coordinator.set_replicator(backend.replicator());
+    let step_down = Arc::new(AtomicBool::new(false));
+    let clone = step_down.clone();
+    thread::spawn(move || read_commands(clone));
 
     loop {
-        if scrum_master.am_i_leader() {
-            if let Some(result) = iter.next() {
-                let mut record: Payment = result.expect("Unable to read record");
-                while record.line <= last_record {
-                    if let Some(result) = iter.next() {
-                        match result {
-                            Err(e) => {
-                                println!("[Reading record threw error] {}", e);
-                            }
-                            Ok(r) => {
-                                record = r;
-                            }
-                        }
-                    }
+        if backend.am_i_leader() {
+            // A new leader learns the progress of its predecessors and finishes the transactions
+            // they left in doubt
+            let epoch = backend.epoch();
+            if coordinator.epoch() != epoch {
+                println!("[{}] Leading epoch {}", id, epoch);
+                while let Some(replicated) = backend.receive(Duration::from_millis(0)) {
+                    apply(&coordinator, &mut last_record, replicated);
                 }
+                checkpoint.save(last_record, backend.epoch());
+                coordinator.lead(epoch);
+            }
 
+            // A leader stepping down submits no more records
+            let stepping_down = step_down.load(Ordering::SeqCst);
+            let next = if stepping_down {
+                None
+            } else {
+                next_record(&mut iter, last_record, &mut finished_lines)
+            };
+            advance(&mut last_record, &mut finished_lines, &input_lines);
+            if let Some(record) = next {
                 println!(
-                    "\n\n\n[Record | {},{},{},{}]",
-                    record.line, record.hotel, record.airline, record.bank
+                    "\n\n\n[Record | {},{} | {}]",
+                    record.line,
+                    record.leg_amounts(&registry),
+                    record.currency
                 );
 
-                let is_successful = coordinator.submit(record.line as i32, record);
+                let handle = coordinator.submit_async(record.line as i32, record.clone());
+                in_flight.push_back((record, handle));
+            } else if in_flight.is_empty() {
+                // The last lines may have been finished whitout being submitted
+                checkpoint.save(last_record, backend.epoch());
+                backend.replicate_last_record(last_record);
+                // Only an explicit step-down hands off the leadership, the followers learn the
+                // last line from the progress already replicated
+                if stepping_down {
+                    println!("[{}] Stepping down", id);
+                    backend.step_down(last_record, &coordinator);
+                } else {
+                    println!("[Reached EOF]");
+                }
+                backend.stop();
+                break;
+            } else {
+                reached_eof = true;
+            }
+
+            // Only waits for a result when no more records can be submitted
+            let mut results = Vec::new();
+            if reached_eof || in_flight.len() >= coordinator.max_in_flight() {
+                let (record, handle) = in_flight.pop_front().expect("No transaction in flight");
+                results.push((record, handle.wait()));
+            }
+            in_flight.retain_mut(|(record, handle)| match handle.poll() {
+                Some(outcome) => {
+                    results.push((record.clone(), outcome));
+                    false
+                }
+                None => true,
+            });
+            if results.is_empty() {
+                continue;
+            }
+
+            for (record, outcome) in results {
+                println!("result of {} was {}", record.line, outcome);
 
-                println!("result was {}", is_successful);
+                // The decision may not have reached the other instances, the line is left for
+                // the next leader whitout reporting it
+                if outcome == TransactionOutcome::InDoubt {
+                    continue;
+                }
 
-                if !is_successful {
-                    let data = format!("{},{},{}\n", record.bank, record.airline, record.hotel);
+                if !outcome.is_committed() {
+                    let data = format!(
+                        "{},{},{},{},{}\n",
+                        record.line,
+                        record.customer_id,
+                        record.currency,
+                        record.leg_amounts(&registry),
+                        outcome
+                    );
                     failed_transactions_file
                         .write_all(data.as_ref())
                         .expect("Error writing to error file")
                 }
 
-                last_record = record.line;
-            } else {
-                println!("[Reached EOF]");
-                scrum_master.stop();
-                break;
+                finished_lines.insert(record.line);
             }
+            advance(&mut last_record, &mut finished_lines, &input_lines);
 
-            socket
-                .set_read_timeout(Some(Duration::new(1, 0)))
-                .expect("set_read_timeout error occurred");
-
-            for peer_id in 0..TEAM_MEMBERS {
-                if peer_id != id {
-                    println!("[{}] Sending to peer last record", id);
-                    socket
-                        .send_to(&last_record.to_be_bytes(), id_to_dataaddr(peer_id))
-                        .expect("Error sending last_record to peers");
-                }
-            }
+            checkpoint.save(last_record, backend.epoch());
+            backend.replicate_last_record(last_record);
         } else {
-            println!(
-                "[{}] Last time I checked last line was {}",
-                id,
-                last_record.to_string()
-            );
-
-            let leader_id = scrum_master.get_leader_id();
-
-            if leader_id != id {
-                socket
-                    .set_read_timeout(Some(TIMEOUT))
-                    .expect("Error setting set_read_timeout in main");
-                if let Ok((_size, from)) = socket.recv_from(&mut buf) {
-                    last_record = usize::from_be_bytes(buf);
-                    if leader_id == TEAM_MEMBERS {
-                        let new_leader = from
-                            .port()
-                            .to_string()
-                            .chars()
-                            .last()
-                            .expect("Error getting id of leader")
-                            .to_digit(10)
-                            .expect("Error casting char to usize")
-                            as usize;
-                        scrum_master.set_leader(new_leader);
-                        println!(
-                            "[{}] Leader is ({}) and last line is {}",
-                            id,
-                            new_leader,
-                            last_record.to_string()
-                        );
-                    } else {
-                        println!(
-                            "[{}] Received from leader ({}) that last line is {}",
-                            id,
-                            leader_id,
-                            last_record.to_string()
-                        );
-                    }
+            println!("[{}] Last time I checked last line was {}", id, last_record);
+
+            // A quiet leader is only busy, its failure is detected by the backend
+            if let Some(replicated) = backend.receive(config.heartbeat_interval()) {
+                let record = matches!(replicated, Replicated::LastRecord(_));
+                apply(&coordinator, &mut last_record, replicated);
+                if record {
+                    checkpoint.save(last_record, backend.epoch());
+                    println!(
+                        "[{}] Received from leader ({}) that last line is {}",
+                        id,
+                        backend.get_leader_id(),
+                        last_record
+                    );
                     if last_record == lines {
-                        scrum_master.stop();
+                        backend.stop();
                         break;
                     }
-                    thread::sleep(Duration::from_millis(500));
-                } else {
-                    scrum_master.find_new()
                 }
             }
         }
     }
+    // The decisions still being sent are left in doubt, the next leader finishes them
+    coordinator.shutdown();
+}
+
+/// Reads the commands typed in the standard input, `step-down` makes the leader hand off the
+/// leadership once its transactions in flight finish
+fn read_commands(step_down: Arc<AtomicBool>) {
+    for line in io::stdin().lock().lines() {
+        match line {
+            Ok(line) if line.trim() == "step-down" => step_down.store(true, Ordering::SeqCst),
+            Ok(line) => println!("Unknown command {}", line.trim()),
+            Err(_) => break,
+        }
+    }
+}
+
+/// Creates the backend chosen in the configuration
+fn new_backend(id: usize, config: &Config) -> Box<dyn ClusterBackend> {
+    match config.election {
+        Election::Bully => Box::new(ElectionBackend::new(
+            id,
+            config,
+            Box::new(LeaderElection::new(id, config)),
+        )),
+        Election::Ring => Box::new(ElectionBackend::new(
+            id,
+            config,
+            Box::new(RingElection::new(id, config)),
+        )),
+        Election::Raft => Box::new(RaftBackend::new(id, config)),
+    }
+}
+
+/// Applies the progress replicated by the leader. The last processed line never goes back, a
+/// restarted instance may have processed more lines than the ones replicated by older leaders
+fn apply(coordinator: &TransactionCoordinator, last_record: &mut usize, replicated: Replicated) {
+    match replicated {
+        Replicated::LastRecord(record) => *last_record = (*last_record).max(record),
+        Replicated::Decision(decision) => coordinator.apply_replicated(decision),
+    }
+}
+
+/// The last record is the last line processed whit every previous line of the payments file
+/// processed, it moves over the finished lines that follow it
+fn advance(
+    last_record: &mut usize,
+    finished_lines: &mut BTreeSet<usize>,
+    input_lines: &BTreeSet<usize>,
+) {
+    while let Some(next) = input_lines.range(*last_record + 1..).next() {
+        if !finished_lines.remove(next) {
+            break;
+        }
+        *last_record = *next;
+    }
+}
+
+/// Returns the next reservation of the payments file whose line was not processed yet. The lines
+/// that can not be read are finished right away, as there is nothing to submit for them
+fn next_record(
+    iter: &mut impl Iterator<Item = PaymentEntry>,
+    last_record: usize,
+    finished_lines: &mut BTreeSet<usize>,
+) -> Option<Reservation> {
+    for (line, result) in iter {
+        match result {
+            Err(e) => {
+                println!("[Reading record threw error] {}", e);
+                if let Some(line) = line.filter(|line| *line > last_record) {
+                    finished_lines.insert(line);
+                }
+            }
+            Ok(record) => {
+                if record.line > last_record {
+                    return Some(record);
+                }
+            }
+        }
+    }
+    None
 }
 
 fn get_failed_transactions_file(failed_transactions_path: &str) -> File {
-    return match fs::OpenOptions::new()
-        .write(true)
+    match fs::OpenOptions::new()
         .append(true)
         .open(failed_transactions_path)
     {
@@ -158,5 +341,5 @@ fn get_failed_transactions_file(failed_transactions_path: &str) -> File {
             println!("Failed transactions file does not exist, will create it");
             fs::File::create(failed_transactions_path).expect("Error creating logger file")
         }
-    };
+    }
 }
@@ -0,0 +1,880 @@
+use crate::backend::{ClusterBackend, Replicated, MAX_MESSAGE_SIZE};
+use crate::leader_election::UNKNOWN_LEADER;
+use common::append_log;
+use common::config::Config;
+use common::decision_log::Decision;
+use common::peer_directory::PeerDirectory;
+use common::replication::Replicator;
+use common::transaction_coordinator::TransactionCoordinator;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::io::Write;
+use std::net::UdpSocket;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// The most entries sent in a single AppendEntries, so the message fits in a datagram
+const MAX_ENTRIES: usize = 16;
+
+/// The role of a Raft node in its current term
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+/// An entry of the replicated log. The entry whitout a command is the one every leader appends
+/// when elected, once it's committed the leader knows every entry of previous terms
+#[derive(Clone, Serialize, Deserialize)]
+struct Entry {
+    term: u64,
+    command: Option<Replicated>,
+}
+
+/// The messages exchanged by the Raft nodes through the control sockets
+#[derive(Serialize, Deserialize)]
+enum Message {
+    RequestVote {
+        term: u64,
+        candidate: usize,
+        last_log_index: usize,
+        last_log_term: u64,
+    },
+    Vote {
+        term: u64,
+        voter: usize,
+        granted: bool,
+    },
+    AppendEntries {
+        term: u64,
+        leader: usize,
+        prev_log_index: usize,
+        prev_log_term: u64,
+        entries: Vec<Entry>,
+        leader_commit: usize,
+    },
+    AppendResponse {
+        term: u64,
+        follower: usize,
+        success: bool,
+        match_index: usize,
+    },
+    /// Sent by a leader stepping down to the follower that takes over
+    TimeoutNow { term: u64, leader: usize },
+}
+
+/// The term, the vote and the last entry applied, which must survive a restart of the node
+#[derive(Serialize, Deserialize)]
+struct PersistentState {
+    term: u64,
+    voted_for: Option<usize>,
+    #[serde(default)]
+    last_applied: usize,
+}
+
+/// Persists the term, the vote and the log of a node. The log is a file whit an entry per line,
+/// written again whole when a leader overwrites part of it
+struct Storage {
+    state_path: String,
+    log_path: String,
+    log_file: File,
+}
+
+impl Storage {
+    /// Opens the files of the node whit the given path prefix, returning the state and the log
+    /// they already contain. The torn entry a crash may have left at the end of the log is cut
+    /// off, so the entries appended next are read again after a restart
+    fn open(prefix: &str, id: usize) -> (Storage, PersistentState, Vec<Entry>) {
+        let state_path = format!("{}{}.state", prefix, id);
+        let log_path = format!("{}{}.log", prefix, id);
+
+        let state = fs::read_to_string(&state_path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or(PersistentState {
+                term: 0,
+                voted_for: None,
+                last_applied: 0,
+            });
+        let (log_file, content) = append_log::open(&log_path).expect("Error opening raft log");
+        let mut log = Vec::new();
+        let mut corrupt = false;
+        for line in content.lines() {
+            match serde_json::from_str(line) {
+                Ok(entry) => log.push(entry),
+                Err(_) => {
+                    println!("[RAFT] dropping the log from corrupt entry {}", line);
+                    corrupt = true;
+                    break;
+                }
+            }
+        }
+
+        let mut storage = Storage {
+            log_file,
+            state_path,
+            log_path,
+        };
+        // The entries after a corrupt one are dropped from the file too, so it matches the log
+        if corrupt {
+            storage.rewrite(&log);
+        }
+        (storage, state, log)
+    }
+
+    /// Persists the term, the vote and the last entry applied. They are written to a temporary
+    /// file that replaces the state once it reaches the disk, so a crash never leaves it torn
+    fn save_state(&self, term: u64, voted_for: Option<usize>, last_applied: usize) {
+        let state = PersistentState {
+            term,
+            voted_for,
+            last_applied,
+        };
+        let tmp_path = format!("{}.tmp", self.state_path);
+        let mut file = File::create(&tmp_path).expect("Error creating raft state");
+        file.write_all(
+            serde_json::to_string(&state)
+                .expect("Error serializing raft state")
+                .as_bytes(),
+        )
+        .expect("Error writing raft state");
+        file.sync_all().expect("Error syncing raft state");
+        fs::rename(&tmp_path, &self.state_path).expect("Error replacing raft state");
+    }
+
+    /// Appends the entries to the log and waits until they reach the disk
+    fn append(&mut self, entries: &[Entry]) {
+        let mut lines = String::new();
+        for entry in entries {
+            lines.push_str(&serde_json::to_string(entry).expect("Error serializing raft entry"));
+            lines.push('\n');
+        }
+        self.log_file
+            .write_all(lines.as_bytes())
+            .expect("Error writing raft log");
+        self.log_file.sync_data().expect("Error syncing raft log");
+    }
+
+    /// Writes again the whole log, used when entries are removed from its end. It's written to a
+    /// temporary file that replaces the log once it reaches the disk, so a crash never leaves
+    /// the log whitout the entries it already had
+    fn rewrite(&mut self, log: &[Entry]) {
+        let tmp_path = format!("{}.tmp", self.log_path);
+        self.log_file = File::create(&tmp_path).expect("Error creating raft log");
+        self.append(log);
+        fs::rename(&tmp_path, &self.log_path).expect("Error replacing raft log");
+        self.log_file = fs::OpenOptions::new()
+            .append(true)
+            .open(&self.log_path)
+            .expect("Error opening raft log");
+    }
+}
+
+/// The state of a node, the log indexes start at 1 as in the Raft paper
+struct RaftState {
+    role: Role,
+    term: u64,
+    voted_for: Option<usize>,
+    leader_id: usize,
+    log: Vec<Entry>,
+    commit_index: usize,
+    last_applied: usize,
+    next_index: HashMap<usize, usize>,
+    match_index: HashMap<usize, usize>,
+    votes: BTreeSet<usize>,
+    election_deadline: Instant,
+    last_broadcast: Instant,
+    storage: Storage,
+    applied: Sender<Replicated>,
+}
+
+impl RaftState {
+    /// Returns the index and the term of the last entry of the log
+    fn last_log(&self) -> (usize, u64) {
+        (
+            self.log.len(),
+            self.log.last().map_or(0, |entry| entry.term),
+        )
+    }
+
+    /// Returns the term of the entry at the given index, 0 for the index before the first one
+    fn term_at(&self, index: usize) -> u64 {
+        if index == 0 {
+            0
+        } else {
+            self.log[index - 1].term
+        }
+    }
+
+    /// Becomes a follower of the given term, forgetting the vote and the leader of older terms
+    fn step_down(&mut self, term: u64) {
+        if term > self.term {
+            self.term = term;
+            self.voted_for = None;
+            self.leader_id = UNKNOWN_LEADER;
+            self.storage
+                .save_state(self.term, self.voted_for, self.last_applied);
+        }
+        self.role = Role::Follower;
+    }
+
+    /// Sends the committed entries to the main loop. The entries of the current term of a leader
+    /// were proposed by that same instance, so they are not sent again. The last entry applied
+    /// is persisted, so a restart does not apply the whole log again
+    fn apply_committed(&mut self) {
+        if self.last_applied >= self.commit_index {
+            return;
+        }
+        while self.last_applied < self.commit_index {
+            self.last_applied += 1;
+            let entry = &self.log[self.last_applied - 1];
+            if self.role == Role::Leader && entry.term == self.term {
+                continue;
+            }
+            if let Some(command) = &entry.command {
+                // The receiver is only gone once the instance is shutting down
+                let _ = self.applied.send(command.clone());
+            }
+        }
+        self.storage
+            .save_state(self.term, self.voted_for, self.last_applied);
+    }
+}
+
+/// A node of the Raft cluster formed by the alGlobo instances. It elects the leader and
+/// replicates the log through the control sockets, every entry is committed once a majority
+/// of the instances stored it
+pub struct RaftNode {
+    id: usize,
+    socket: UdpSocket,
+    peers: Arc<PeerDirectory>,
+    heartbeat_interval: Duration,
+    election_timeout: Duration,
+    state: Arc<(Mutex<RaftState>, Condvar)>,
+    stop: Arc<Mutex<bool>>,
+    threads: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl RaftNode {
+    /// Creates the node whit the given id and starts taking part in the elections, the committed
+    /// entries are sent through the returned receiver
+    pub fn new(id: usize, config: &Config) -> (RaftNode, Receiver<Replicated>) {
+        let peers = config.directory();
+        let (storage, persistent, log) = Storage::open(&config.paths.raft_log, id);
+        // The entries applied before a restart were committed, a torn log may have lost them
+        let last_applied = persistent.last_applied.min(log.len());
+        let (applied, receiver) = mpsc::channel();
+        let heartbeat_interval = config.heartbeat_interval();
+        let socket = UdpSocket::bind(peers.get(id).expect("Unknown peer").ctrl)
+            .expect("Unable to bind socket for RaftNode");
+        socket
+            .set_read_timeout(Some(heartbeat_interval))
+            .expect("Error setting set_read_timeout in RaftNode");
+
+        let node = RaftNode {
+            id,
+            socket,
+            peers: Arc::new(peers),
+            heartbeat_interval,
+            election_timeout: heartbeat_interval * config.timeouts.missed_heartbeats.max(1),
+            state: Arc::new((
+                Mutex::new(RaftState {
+                    role: Role::Follower,
+                    term: persistent.term,
+                    voted_for: persistent.voted_for,
+                    leader_id: UNKNOWN_LEADER,
+                    log,
+                    commit_index: last_applied,
+                    last_applied,
+                    next_index: HashMap::new(),
+                    match_index: HashMap::new(),
+                    votes: BTreeSet::new(),
+                    election_deadline: Instant::now(),
+                    last_broadcast: Instant::now(),
+                    storage,
+                    applied,
+                }),
+                Condvar::new(),
+            )),
+            stop: Arc::new(Mutex::new(false)),
+            threads: Arc::new(Mutex::new(Vec::new())),
+        };
+        node.reset_election_deadline(&mut node.state.0.lock().expect("state is poisoned"));
+
+        let mut receiver_node = node.clone();
+        let mut ticker_node = node.clone();
+        *node.threads.lock().expect("threads is poisoned") = vec![
+            thread::spawn(move || receiver_node.receiver()),
+            thread::spawn(move || ticker_node.ticker()),
+        ];
+
+        (node, receiver)
+    }
+
+    /// Returns true if the node is the leader and already committed an entry of its term, so it
+    /// knows every entry committed by the previous leaders
+    pub fn am_i_leader(&self) -> bool {
+        let state = self.state.0.lock().expect("state is poisoned");
+        state.role == Role::Leader
+            && state.commit_index > 0
+            && state.term_at(state.commit_index) == state.term
+    }
+
+    /// Returns the id of the current leader, UNKNOWN_LEADER if it's unknown
+    pub fn get_leader_id(&self) -> usize {
+        self.state.0.lock().expect("state is poisoned").leader_id
+    }
+
+    /// Returns the current term
+    pub fn term(&self) -> u64 {
+        self.state.0.lock().expect("state is poisoned").term
+    }
+
+    /// Appends the command to the log if this node is the leader of the given term, returning
+    /// the index of the new entry
+    pub fn propose(&self, command: Replicated, term: u64) -> Option<usize> {
+        let mut state = self.state.0.lock().expect("state is poisoned");
+        if state.role != Role::Leader || state.term != term {
+            return None;
+        }
+        let entry = Entry {
+            term,
+            command: Some(command),
+        };
+        state.storage.append(std::slice::from_ref(&entry));
+        state.log.push(entry);
+        let index = state.log.len();
+        self.send_append_entries_to_all(&mut state);
+        Some(index)
+    }
+
+    /// Waits up to the given time until the entry at the given index of the given term is
+    /// committed, returns false if it was not
+    pub fn wait_committed(&self, index: usize, term: u64, timeout: Duration) -> bool {
+        let (state, _) = self
+            .state
+            .1
+            .wait_timeout_while(
+                self.state.0.lock().expect("state is poisoned"),
+                timeout,
+                |state| state.commit_index < index && state.term == term,
+            )
+            .expect("state is poisoned");
+        state.commit_index >= index && state.term_at(index) == term
+    }
+
+    /// Makes the follower whit the highest id among the ones that stored the whole log start an
+    /// election right away, which it wins as no other log is more up to date. Returns false if
+    /// this node is not the leader or no follower is up to date
+    pub fn transfer_leadership(&self) -> bool {
+        let state = self.state.0.lock().expect("state is poisoned");
+        if state.role != Role::Leader {
+            return false;
+        }
+        let last = state.log.len();
+        let target = state
+            .match_index
+            .iter()
+            .filter(|(_, match_index)| **match_index == last)
+            .map(|(peer_id, _)| *peer_id)
+            .max();
+        match target {
+            Some(target) => {
+                println!("[{}] Handing off the leadership to {}", self.id, target);
+                let message = Message::TimeoutNow {
+                    term: state.term,
+                    leader: self.id,
+                };
+                self.send(target, &message);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Stops the node and waits for its threads. A leader first waits a bounded time for every
+    /// follower to store and commit its whole log, so they learn the last line processed before
+    /// it goes away
+    pub fn stop(&mut self) {
+        let deadline = Instant::now() + self.election_timeout;
+        let mut state = self.state.0.lock().expect("state is poisoned");
+        while state.role == Role::Leader && Instant::now() < deadline {
+            let last = state.log.len();
+            if state.commit_index == last && state.match_index.values().all(|index| *index == last)
+            {
+                // One more round so the followers learn the last commit index
+                self.send_append_entries_to_all(&mut state);
+                break;
+            }
+            state = self
+                .state
+                .1
+                .wait_timeout(state, self.heartbeat_interval)
+                .expect("state is poisoned")
+                .0;
+        }
+        drop(state);
+        *self.stop.lock().expect("stop is poisoned") = true;
+        let threads: Vec<JoinHandle<()>> = self
+            .threads
+            .lock()
+            .expect("threads is poisoned")
+            .drain(..)
+            .collect();
+        for handle in threads {
+            if handle.join().is_err() {
+                println!("[{}] A Raft thread panicked", self.id);
+            }
+        }
+    }
+
+    /// Returns true once the node was stopped
+    fn stopped(&self) -> bool {
+        *self.stop.lock().expect("stop is poisoned")
+    }
+
+    /// Picks a random election deadline, so the nodes rarely start an election at the same time
+    fn reset_election_deadline(&self, state: &mut RaftState) {
+        let base = self.election_timeout.as_millis().max(1) as u64;
+        let timeout = rand::thread_rng().gen_range(base, 2 * base);
+        state.election_deadline = Instant::now() + Duration::from_millis(timeout);
+    }
+
+    /// Returns the amount of nodes that make a majority
+    fn majority(&self) -> usize {
+        self.peers.len() / 2 + 1
+    }
+
+    /// Sends the message to the node whit the given id
+    fn send(&self, to: usize, message: &Message) {
+        let peer = match self.peers.get(to) {
+            Some(peer) => peer,
+            None => {
+                println!("[{}] Unknown peer {}", self.id, to);
+                return;
+            }
+        };
+        let msg = serde_json::to_vec(message).expect("Error serializing raft message");
+        if let Err(e) = self.socket.send_to(&msg, peer.ctrl) {
+            println!("[{}] Error sending raft message to {}: {}", self.id, to, e);
+        }
+    }
+
+    /// Starts an election for the next term, voting for itself
+    fn start_election(&self, state: &mut RaftState) {
+        state.role = Role::Candidate;
+        state.term += 1;
+        state.voted_for = Some(self.id);
+        state.leader_id = UNKNOWN_LEADER;
+        state.votes = BTreeSet::new();
+        state.votes.insert(self.id);
+        state
+            .storage
+            .save_state(state.term, state.voted_for, state.last_applied);
+        self.reset_election_deadline(state);
+        println!("[{}] Starting election for term {}", self.id, state.term);
+
+        if state.votes.len() >= self.majority() {
+            self.become_leader(state);
+            return;
+        }
+        let (last_log_index, last_log_term) = state.last_log();
+        let request = Message::RequestVote {
+            term: state.term,
+            candidate: self.id,
+            last_log_index,
+            last_log_term,
+        };
+        for peer_id in self.peers.ids().filter(|peer_id| *peer_id != self.id) {
+            self.send(peer_id, &request);
+        }
+    }
+
+    /// Becomes the leader of the current term and appends the entry whitout command, which
+    /// commits the entries of the previous terms once it's committed
+    fn become_leader(&self, state: &mut RaftState) {
+        println!("[{}] Leader for term {}", self.id, state.term);
+        state.role = Role::Leader;
+        state.leader_id = self.id;
+        let entry = Entry {
+            term: state.term,
+            command: None,
+        };
+        state.storage.append(std::slice::from_ref(&entry));
+        state.log.push(entry);
+        state.next_index = HashMap::new();
+        state.match_index = HashMap::new();
+        for peer_id in self.peers.ids().filter(|peer_id| *peer_id != self.id) {
+            state.next_index.insert(peer_id, state.log.len());
+            state.match_index.insert(peer_id, 0);
+        }
+        self.advance_commit_index(state);
+        self.send_append_entries_to_all(state);
+    }
+
+    /// Sends to the follower the entries it's missing, at most MAX_ENTRIES of them
+    fn send_append_entries(&self, state: &RaftState, to: usize) {
+        let next_index = *state.next_index.get(&to).unwrap_or(&1);
+        let prev_log_index = next_index - 1;
+        let end = state.log.len().min(prev_log_index + MAX_ENTRIES);
+        let message = Message::AppendEntries {
+            term: state.term,
+            leader: self.id,
+            prev_log_index,
+            prev_log_term: state.term_at(prev_log_index),
+            entries: state.log[prev_log_index..end].to_vec(),
+            leader_commit: state.commit_index,
+        };
+        self.send(to, &message);
+    }
+
+    /// Sends AppendEntries to every follower, it also works as the heartbeat of the leader
+    fn send_append_entries_to_all(&self, state: &mut RaftState) {
+        for peer_id in self.peers.ids().filter(|peer_id| *peer_id != self.id) {
+            self.send_append_entries(state, peer_id);
+        }
+        state.last_broadcast = Instant::now();
+    }
+
+    /// Commits the newest entry of the current term stored by a majority, together whit every
+    /// entry before it, and applies them
+    fn advance_commit_index(&self, state: &mut RaftState) {
+        let previous = state.commit_index;
+        for index in (state.commit_index + 1..=state.log.len()).rev() {
+            if state.term_at(index) != state.term {
+                break;
+            }
+            let stored = 1 + state
+                .match_index
+                .values()
+                .filter(|match_index| **match_index >= index)
+                .count();
+            if stored >= self.majority() {
+                state.commit_index = index;
+                break;
+            }
+        }
+        if state.commit_index != previous {
+            state.apply_committed();
+            self.state.1.notify_all();
+        }
+    }
+
+    /// Handles a message received from another node
+    fn handle(&self, state: &mut RaftState, message: Message) {
+        match message {
+            Message::RequestVote {
+                term,
+                candidate,
+                last_log_index,
+                last_log_term,
+            } => {
+                if term > state.term {
+                    state.step_down(term);
+                }
+                let (my_last_index, my_last_term) = state.last_log();
+                let up_to_date = last_log_term > my_last_term
+                    || (last_log_term == my_last_term && last_log_index >= my_last_index);
+                let granted = term == state.term
+                    && up_to_date
+                    && state.voted_for.is_none_or(|voted| voted == candidate);
+                if granted {
+                    state.voted_for = Some(candidate);
+                    state
+                        .storage
+                        .save_state(state.term, state.voted_for, state.last_applied);
+                    self.reset_election_deadline(state);
+                    println!("[{}] Voting {} for term {}", self.id, candidate, term);
+                }
+                let vote = Message::Vote {
+                    term: state.term,
+                    voter: self.id,
+                    granted,
+                };
+                self.send(candidate, &vote);
+            }
+            Message::Vote {
+                term,
+                voter,
+                granted,
+            } => {
+                if term > state.term {
+                    state.step_down(term);
+                } else if granted && term == state.term && state.role == Role::Candidate {
+                    state.votes.insert(voter);
+                    if state.votes.len() >= self.majority() {
+                        self.become_leader(state);
+                    }
+                }
+            }
+            Message::AppendEntries {
+                term,
+                leader,
+                prev_log_index,
+                prev_log_term,
+                entries,
+                leader_commit,
+            } => {
+                if term < state.term {
+                    let response = Message::AppendResponse {
+                        term: state.term,
+                        follower: self.id,
+                        success: false,
+                        match_index: 0,
+                    };
+                    self.send(leader, &response);
+                    return;
+                }
+                state.step_down(term);
+                if state.leader_id != leader {
+                    println!("[{}] Leader is {} for term {}", self.id, leader, term);
+                    state.leader_id = leader;
+                }
+                self.reset_election_deadline(state);
+
+                if prev_log_index > state.log.len()
+                    || state.term_at(prev_log_index) != prev_log_term
+                {
+                    // Tells the leader where to continue looking for the last common entry
+                    let response = Message::AppendResponse {
+                        term: state.term,
+                        follower: self.id,
+                        success: false,
+                        match_index: state.log.len().min(prev_log_index.saturating_sub(1)),
+                    };
+                    self.send(leader, &response);
+                    return;
+                }
+
+                let match_index = prev_log_index + entries.len();
+                let mut truncated = false;
+                let mut new_entries = Vec::new();
+                for (offset, entry) in entries.into_iter().enumerate() {
+                    let index = prev_log_index + 1 + offset;
+                    if index <= state.log.len() {
+                        if state.term_at(index) == entry.term {
+                            continue;
+                        }
+                        state.log.truncate(index - 1);
+                        truncated = true;
+                    }
+                    new_entries.push(entry.clone());
+                    state.log.push(entry);
+                }
+                if truncated {
+                    let log = state.log.clone();
+                    state.storage.rewrite(&log);
+                } else if !new_entries.is_empty() {
+                    state.storage.append(&new_entries);
+                }
+
+                if leader_commit > state.commit_index {
+                    state.commit_index = leader_commit.min(match_index);
+                    state.apply_committed();
+                    self.state.1.notify_all();
+                }
+                let response = Message::AppendResponse {
+                    term: state.term,
+                    follower: self.id,
+                    success: true,
+                    match_index,
+                };
+                self.send(leader, &response);
+            }
+            Message::AppendResponse {
+                term,
+                follower,
+                success,
+                match_index,
+            } => {
+                if term > state.term {
+                    state.step_down(term);
+                    return;
+                }
+                if state.role != Role::Leader || term != state.term {
+                    return;
+                }
+                if success {
+                    let matched = state.match_index.entry(follower).or_insert(0);
+                    *matched = (*matched).max(match_index);
+                    let next_index = *matched + 1;
+                    state.next_index.insert(follower, next_index);
+                    self.advance_commit_index(state);
+                    if next_index <= state.log.len() {
+                        self.send_append_entries(state, follower);
+                    }
+                    self.state.1.notify_all();
+                } else {
+                    let next_index = state.next_index.entry(follower).or_insert(1);
+                    *next_index = (*next_index - 1).min(match_index + 1).max(1);
+                    self.send_append_entries(state, follower);
+                }
+            }
+            Message::TimeoutNow { term, leader } => {
+                if term == state.term && state.role == Role::Follower {
+                    println!("[{}] Leader {} stepped down", self.id, leader);
+                    self.start_election(state);
+                }
+            }
+        }
+    }
+
+    /// Receives the messages of the other nodes until the node is stopped
+    fn receiver(&mut self) {
+        let mut buf = vec![0; MAX_MESSAGE_SIZE];
+        while !self.stopped() {
+            let size = match self.socket.recv_from(&mut buf) {
+                Ok((size, _from)) => size,
+                // The read timeout only gives the chance to check if the node was stopped
+                Err(_) => continue,
+            };
+            let message = match serde_json::from_slice(&buf[..size]) {
+                Ok(message) => message,
+                Err(_) => {
+                    println!("[{}] Received a malformed raft message", self.id);
+                    continue;
+                }
+            };
+            let mut state = self.state.0.lock().expect("state is poisoned");
+            self.handle(&mut state, message);
+        }
+    }
+
+    /// Sends the heartbeats while this node is the leader, otherwise starts an election once the
+    /// election deadline passes whitout hearing from a leader
+    fn ticker(&mut self) {
+        while !self.stopped() {
+            thread::sleep(self.heartbeat_interval / 4);
+            let mut state = self.state.0.lock().expect("state is poisoned");
+            if state.role == Role::Leader {
+                if state.last_broadcast.elapsed() >= self.heartbeat_interval {
+                    self.send_append_entries_to_all(&mut state);
+                }
+            } else if Instant::now() >= state.election_deadline {
+                if state.role == Role::Follower && state.leader_id != UNKNOWN_LEADER {
+                    println!(
+                        "[{}] Leader {} missed its heartbeats",
+                        self.id, state.leader_id
+                    );
+                }
+                self.start_election(&mut state);
+            }
+        }
+    }
+
+    /// clones the RaftNode
+    fn clone(&self) -> RaftNode {
+        RaftNode {
+            id: self.id,
+            socket: self.socket.try_clone().expect("Error while cloning socket"),
+            peers: self.peers.clone(),
+            heartbeat_interval: self.heartbeat_interval,
+            election_timeout: self.election_timeout,
+            state: self.state.clone(),
+            stop: self.stop.clone(),
+            threads: self.threads.clone(),
+        }
+    }
+}
+
+/// Replicates the decisions of the coordinator as entries of the Raft log, waiting until a
+/// majority of the instances stored them
+pub struct RaftReplicator {
+    node: RaftNode,
+}
+
+impl Replicator for RaftReplicator {
+    fn replicate(&self, decision: &Decision, epoch: u64) -> Result<(), String> {
+        let index = self
+            .node
+            .propose(Replicated::Decision(decision.clone()), epoch)
+            .ok_or(format!("epoch {} is no longer leading", epoch))?;
+        if !self
+            .node
+            .wait_committed(index, epoch, self.node.election_timeout)
+        {
+            return Err("it was not stored by a majority".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Raft election whit the progress of the leader replicated in the Raft log, the epoch of a
+/// leader is its term
+pub struct RaftBackend {
+    node: RaftNode,
+    applied: Receiver<Replicated>,
+}
+
+impl RaftBackend {
+    /// Creates the backend of the instance whit the given id, it binds its control socket
+    pub fn new(id: usize, config: &Config) -> RaftBackend {
+        let (node, applied) = RaftNode::new(id, config);
+        RaftBackend { node, applied }
+    }
+}
+
+impl ClusterBackend for RaftBackend {
+    fn am_i_leader(&self) -> bool {
+        self.node.am_i_leader()
+    }
+
+    fn get_leader_id(&self) -> usize {
+        self.node.get_leader_id()
+    }
+
+    fn epoch(&self) -> u64 {
+        self.node.term()
+    }
+
+    fn replicate_last_record(&self, last_record: usize) {
+        let term = self.node.term();
+        if self
+            .node
+            .propose(Replicated::LastRecord(last_record), term)
+            .is_none()
+        {
+            println!(
+                "[{}] Not leading anymore, last record not replicated",
+                self.node.id
+            );
+        }
+    }
+
+    fn replicator(&self) -> Arc<dyn Replicator> {
+        Arc::new(RaftReplicator {
+            node: self.node.clone(),
+        })
+    }
+
+    fn receive(&mut self, timeout: Duration) -> Option<Replicated> {
+        self.applied.recv_timeout(timeout).ok()
+    }
+
+    /// Waits until the last processed line is committed and makes the most up to date follower
+    /// take over, the decisions of the coordinator are already in the log
+    fn step_down(&mut self, last_record: usize, _coordinator: &TransactionCoordinator) {
+        let term = self.node.term();
+        let committed = match self.node.propose(Replicated::LastRecord(last_record), term) {
+            Some(index) => self
+                .node
+                .wait_committed(index, term, self.node.election_timeout),
+            None => false,
+        };
+        if !committed || !self.node.transfer_leadership() {
+            println!("[{}] No instance took over the leadership", self.node.id);
+        }
+    }
+
+    /// The term is already restored from the Raft state
+    fn restore_epoch(&mut self, _epoch: u64) {}
+
+    fn stop(&mut self) {
+        self.node.stop();
+    }
+}
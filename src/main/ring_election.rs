@@ -0,0 +1,324 @@
+use crate::election_core::ElectionCore;
+use crate::leader_election::LeaderElectionStrategy;
+use common::config::Config;
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::mem::size_of;
+use std::sync::{Arc, Condvar, Mutex};
+
+/// The size of every message: header, id of the sender, its epoch and the id it carries
+const MSG_SIZE: usize = 1 + size_of::<u64>() * 3;
+
+/// The acknowledgements received and not consumed yet: the peer that sent it, the header of the
+/// acknowledged message and the id it carried
+type Acks = HashSet<(usize, u8, u64)>;
+
+/// Chang-Roberts election: the instances form a ring ordered by id and the election message
+/// travels around it carrying the greatest id seen, once it gets back to that instance it's the
+/// leader and announces itself around the ring. Every message passed along the ring is
+/// acknowledged, an instance that does not acknowledge it is skipped
+pub struct RingElection {
+    core: ElectionCore,
+    participant: Arc<Mutex<bool>>,
+    acks: Arc<(Mutex<Acks>, Condvar)>,
+}
+
+impl RingElection {
+    /// Creates a new instance of RingElection, whit the peers and the timeout of the configuration
+    pub fn new(id: usize, config: &Config) -> RingElection {
+        let ret = RingElection {
+            core: ElectionCore::new(id, config),
+            participant: Arc::new(Mutex::new(false)),
+            acks: Arc::new((Mutex::new(HashSet::new()), Condvar::new())),
+        };
+
+        let clone = ret.clone();
+        ret.core.spawn(move || clone.responder());
+        let mut clone = ret.clone();
+        ret.core.spawn(move || {
+            let core = clone.core.clone();
+            core.heartbeater(&mut clone, |me| me.to_msg(b'H', me.epoch(), me.core.id))
+        });
+
+        ret
+    }
+
+    /// Forms a message whit the id of the peer, the epoch, the id it carries and a byte that
+    /// represents the message
+    fn to_msg(&self, header: u8, epoch: u64, value: usize) -> Vec<u8> {
+        let mut msg = vec![header];
+        msg.extend_from_slice(&(self.core.id as u64).to_le_bytes());
+        msg.extend_from_slice(&epoch.to_le_bytes());
+        msg.extend_from_slice(&(value as u64).to_le_bytes());
+        msg
+    }
+
+    /// Passes the message to the next instance of the ring that acknowledges it. Returns false if
+    /// no other instance acknowledged it
+    fn pass_on(&self, header: u8, epoch: u64, value: usize) -> bool {
+        let successors = self
+            .core
+            .peers
+            .ids()
+            .filter(|id| *id > self.core.id)
+            .chain(self.core.peers.ids().filter(|id| *id < self.core.id))
+            .collect::<Vec<usize>>();
+        for successor in successors {
+            if self.send_acked(successor, header, epoch, value) {
+                return true;
+            }
+            println!(
+                "[{}] Skipping {}, it did not acknowledge",
+                self.core.id, successor
+            );
+        }
+        false
+    }
+
+    /// Sends the message to the given peer and waits a heartbeat interval for its
+    /// acknowledgement, returns false if it did not arrive
+    fn send_acked(&self, to: usize, header: u8, epoch: u64, value: usize) -> bool {
+        let peer = self.core.peers.get(to).expect("Unknown peer");
+        if let Err(e) = self
+            .core
+            .socket
+            .send_to(&self.to_msg(header, epoch, value), peer.ctrl)
+        {
+            println!("[{}] Error sending to {}: {}", self.core.id, to, e);
+            return false;
+        }
+        let ack = (to, header, value as u64);
+        let (mut acks, _) = self
+            .acks
+            .1
+            .wait_timeout_while(
+                self.acks.0.lock().expect("acks is poisoned"),
+                self.core.heartbeat_interval(),
+                |acks| !acks.contains(&ack),
+            )
+            .expect("acks is poisoned");
+        acks.remove(&ack)
+    }
+
+    /// Informs the other peers that this instance is the leader of an epoch greater than the
+    /// given one, which is the greatest epoch seen by the election. A stopped instance does not
+    /// become the leader
+    fn make_me_leader(&self, seen_epoch: u64) {
+        let epoch = match self.core.claim_leadership(seen_epoch) {
+            Some(epoch) => epoch,
+            None => return,
+        };
+        *self.participant.lock().expect("participant is poisoned") = false;
+        self.pass_on(b'C', epoch, self.core.id);
+    }
+
+    /// Follows the sender as the leader and leaves the election, returns false if the sender is
+    /// a deposed leader
+    fn follow(&self, id_from: usize, epoch: u64) -> bool {
+        if !self.core.follow(id_from, epoch) {
+            return false;
+        }
+        *self.participant.lock().expect("participant is poisoned") = false;
+        true
+    }
+
+    /// Handles an election message that went around the ring up to this instance. A candidate
+    /// greater than this instance is passed on, a smaller one is replaced by this instance unless
+    /// it's already taking part in the election, and if the candidate is this instance it won
+    fn on_election(&self, candidate: usize, epoch: u64) {
+        let epoch = epoch.max(self.epoch());
+        if candidate == self.core.id {
+            self.make_me_leader(epoch);
+            return;
+        }
+        let mut participant = self.participant.lock().expect("participant is poisoned");
+        let candidate = if candidate > self.core.id {
+            candidate
+        } else if !*participant {
+            self.core.id
+        } else {
+            return;
+        };
+        *participant = true;
+        drop(participant);
+        if !self.pass_on(b'E', epoch, candidate) {
+            // Every other instance is down
+            self.make_me_leader(epoch);
+        }
+    }
+
+    /// Receives the messages of the peers and responds accordingly. Messages passed along the
+    /// ring and handoffs are acknowledged and handled in a new thread, so the acknowledgements of the
+    /// messages this instance passes on keep arriving. Coordinators and heartbeats of deposed
+    /// leaders are rejected
+    fn responder(&self) {
+        let mut buf = [0; MSG_SIZE];
+        while let Some((size, from)) = self.core.receive(&mut buf) {
+            if size != MSG_SIZE {
+                println!("[{}] Received a malformed message", self.core.id);
+                continue;
+            }
+            let id_from =
+                u64::from_le_bytes(buf[1..9].try_into().expect("Error getting id_from")) as usize;
+            let epoch = u64::from_le_bytes(buf[9..17].try_into().expect("Error getting epoch"));
+            let value =
+                u64::from_le_bytes(buf[17..].try_into().expect("Error getting value")) as usize;
+            match &buf[0] {
+                // An acknowledgement carries the header of the acknowledged message in place of
+                // the epoch
+                b'A' => {
+                    let header = epoch as u8;
+                    self.acks.0.lock().expect("acks is poisoned").insert((
+                        id_from,
+                        header,
+                        value as u64,
+                    ));
+                    self.acks.1.notify_all();
+                }
+                header @ (b'E' | b'C' | b'S') => {
+                    let header = *header;
+                    if let Err(e) = self
+                        .core
+                        .socket
+                        .send_to(&self.to_msg(b'A', header as u64, value), from)
+                    {
+                        println!("[{}] Error sending ack: {}", self.core.id, e);
+                    }
+                    let me = self.clone();
+                    if header == b'S' {
+                        if epoch < self.epoch() {
+                            println!(
+                                "[{}] Rejecting handoff of deposed leader {}, epoch {} is stale",
+                                self.core.id, id_from, epoch
+                            );
+                        } else {
+                            println!(
+                                "[{}] Leader {} handed off the leadership whit last line {}",
+                                self.core.id, id_from, value
+                            );
+                            self.core.set_handoff(Some(value));
+                        }
+                    } else if header == b'E' {
+                        println!(
+                            "[{}] Received election of {} from {}",
+                            self.core.id, value, id_from
+                        );
+                        self.core.spawn(move || me.on_election(value, epoch));
+                    } else if value != self.core.id {
+                        println!(
+                            "[{}] Received new coordinator {} for epoch {}",
+                            self.core.id, value, epoch
+                        );
+                        if self.follow(value, epoch) {
+                            self.core.spawn(move || {
+                                me.pass_on(b'C', epoch, value);
+                            });
+                        } else {
+                            println!(
+                                "[{}] Rejecting coordinator {}, epoch {} is stale",
+                                self.core.id, value, epoch
+                            );
+                        }
+                    }
+                }
+                b'H' => {
+                    if !self.follow(id_from, epoch) {
+                        println!(
+                            "[{}] Rejecting heartbeat of deposed leader {}, epoch {} is stale",
+                            self.core.id, id_from, epoch
+                        );
+                    }
+                }
+                _ => {
+                    println!("[{}] Unknown message from {}", self.core.id, id_from);
+                }
+            }
+        }
+    }
+
+    /// clones the RingElection
+    fn clone(&self) -> RingElection {
+        RingElection {
+            core: self.core.clone(),
+            participant: self.participant.clone(),
+            acks: self.acks.clone(),
+        }
+    }
+}
+
+impl LeaderElectionStrategy for RingElection {
+    fn am_i_leader(&self) -> bool {
+        self.get_leader_id() == self.core.id
+    }
+
+    fn get_leader_id(&self) -> usize {
+        self.core.get_leader_id()
+    }
+
+    fn epoch(&self) -> u64 {
+        self.core.epoch()
+    }
+
+    /// Sends an election whit this instance as candidate around the ring and waits for the
+    /// coordinator. If none arrives before the timeout the leader is left unknown, so a new
+    /// election starts once the heartbeats are missed again
+    fn find_new(&mut self) {
+        if !self.core.start_election() {
+            return;
+        }
+        *self.participant.lock().expect("participant is poisoned") = true;
+        if !self.pass_on(b'E', self.epoch(), self.core.id) {
+            // Every other instance is down
+            self.make_me_leader(self.epoch());
+            return;
+        }
+
+        if !self.core.wait_leader(self.core.timeout) {
+            println!("[{}] The election did not finish", self.core.id);
+            *self.participant.lock().expect("participant is poisoned") = false;
+        }
+    }
+
+    fn set_leader(&mut self, id: usize) {
+        self.core.set_leader(id);
+    }
+
+    /// Sends the handoff to the other peers from the highest id down, until one acknowledges it
+    fn step_down(&mut self, last_record: usize) -> bool {
+        let mut peer_ids: Vec<usize> = self
+            .core
+            .peers
+            .others(self.core.id)
+            .map(|(id, _)| id)
+            .collect();
+        peer_ids.reverse();
+        for peer_id in peer_ids {
+            println!(
+                "[{}] Handing off the leadership to {}",
+                self.core.id, peer_id
+            );
+            if self.send_acked(peer_id, b'S', self.epoch(), last_record) {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn handoff(&self) -> Option<usize> {
+        self.core.handoff()
+    }
+
+    fn take_over(&mut self) {
+        self.core.set_handoff(None);
+        self.make_me_leader(self.epoch());
+    }
+
+    fn restore_epoch(&mut self, epoch: u64) {
+        self.core.observe(epoch);
+    }
+
+    /// Stops the threads, waking up the ones waiting for a leader, and joins them
+    fn stop(&mut self) {
+        self.core.stop(|| {});
+    }
+}
@@ -1,34 +1,57 @@
-use common::payment::Payment;
+use common::checkpoint::Checkpoint;
+use common::config::Config;
+use common::money::{Currency, Money};
+use common::reservation::{Leg, Reservation};
 use common::transaction_coordinator;
+use common::transaction_outcome::TransactionOutcome;
 use std::io;
+use structopt::StructOpt;
 
+/// Receives the configuration of the manual processing.
+#[derive(StructOpt)]
+struct Cli {
+    /// The id of the alGlobo instance whose coordinator address and decision log are used.
+    #[structopt(long, default_value = "0")]
+    id: usize,
+    /// The cluster configuration file, a TOML or a JSON file.
+    #[structopt(long)]
+    config: Option<String>,
+    /// The path of the decision log minus the id and the extension.
+    #[structopt(long)]
+    decision_log: Option<String>,
+    /// The election epoch sent to the microservices, it must not be older than the epoch of the
+    /// last alGlobo leader or the microservices reject the transactions. Defaults to the newest
+    /// epoch in the checkpoint of the instance.
+    #[structopt(long)]
+    epoch: Option<u64>,
+}
 
-/// Receives transaction amount
-pub fn get_amount(service: &str) -> i32 {
+/// Receives transaction amount, as a decimal number in the given currency
+pub fn get_amount(service: &str, currency: Currency) -> Money {
     let mut line = String::new();
     let error_message = "[Main] Expected a number greater than zero.";
 
-    println!("[Main] Enter {} amount", service);
+    println!("[Main] Enter {} amount in {}", service, currency);
 
     io::stdin()
         .read_line(&mut line)
         .expect("failed to read from stdin");
     if line.trim().is_empty() {
         println!("{}", error_message);
-        get_amount(service)
+        get_amount(service, currency)
     } else {
-        match line.trim().parse::<u32>() {
-            Ok(last_transaction) => {
-                if last_transaction > 0 {
-                    last_transaction as i32
+        match Money::parse(line.trim(), currency) {
+            Ok(amount) => {
+                if amount.is_positive() {
+                    amount
                 } else {
                     println!("{}", error_message);
-                    get_amount(service)
+                    get_amount(service, currency)
                 }
             }
-            Err(..) => {
-                println!("{}", error_message);
-                get_amount(service)
+            Err(e) => {
+                println!("[Main] {}. {}", e, error_message);
+                get_amount(service, currency)
             }
         }
     }
@@ -67,28 +90,65 @@ pub fn get_last_transaction_id() -> i32 {
 
 /// Manual processing main
 fn main() {
-    let mut coordinator = transaction_coordinator::TransactionCoordinator::new(0);
+    let args = Cli::from_args();
+    let config = Config::load_or_default(args.config.as_deref())
+        .and_then(|config| config.check_peer(args.id).map(|_| config));
+    let mut config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            println!("[Main] Invalid configuration: {}", e);
+            return;
+        }
+    };
+    if let Some(decision_log) = args.decision_log {
+        config.paths.decision_log = decision_log;
+    }
+    let registry = config.registry();
+    let id = args.id;
+    let epoch = args
+        .epoch
+        .unwrap_or_else(|| Checkpoint::open(&config.paths.checkpoint, id).epoch());
+    println!("[Main] Using epoch {}", epoch);
+    let coordinator = transaction_coordinator::TransactionCoordinator::new(args.id, &config);
+    coordinator.lead(epoch);
 
     let mut transaction_id = get_last_transaction_id() + 1;
 
+    let currency = Currency::default();
+
     loop {
-        let bank = get_amount("bank");
-        let airline = get_amount("airline");
-        let hotel = get_amount("hotel");
+        let legs = registry
+            .iter()
+            .map(|participant| Leg {
+                service: participant.id,
+                amount: get_amount(&participant.name.to_lowercase(), currency),
+                reference: String::new(),
+            })
+            .collect();
 
-        let payment = Payment {
+        let reservation = Reservation {
             line: transaction_id as usize,
-            bank,
-            airline,
-            hotel,
+            customer_id: String::new(),
+            currency,
+            legs,
         };
 
-        let result = coordinator.submit(transaction_id, payment);
+        let outcome = coordinator.submit(transaction_id, &reservation);
 
-        if result {
-            println!("Successful transaction")
+        if outcome.is_committed() {
+            println!("Successful transaction: {}", outcome)
         } else {
-            println!("Transaction failed")
+            println!("Transaction failed: {}", outcome)
+        }
+        // The microservices drop the messages of older epochs whitout answering
+        if let TransactionOutcome::AbortedByTimeout { services } = &outcome {
+            if services.len() == reservation.legs.len() {
+                println!(
+                    "[Main] No microservice answered, if they are running epoch {} is older than \
+                     the one of the last leader, run again whit a newer --epoch",
+                    epoch
+                );
+            }
         }
 
         transaction_id += 1;
@@ -1,30 +1,31 @@
-use common::transaction::{Transaction, TransactionState};
+mod participant_log;
+
+use common::money::{Currency, Money};
+use common::transaction::{Transaction, TransactionState, MAX_FRAME_SIZE};
 use std::collections::HashMap;
-use std::net::UdpSocket;
+use std::convert::TryFrom;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
 use structopt::StructOpt;
 
-use common::helper::id_to_microservice;
+use crate::participant_log::ParticipantLog;
+use common::config::Config;
 use rand::Rng;
 
-/// Receives the id of the microservice and return the name of the microservice
-fn id_to_microservice_name(id: usize) -> String {
-    let result = match id {
-        0 => "Bank",
-        1 => "Airline",
-        2 => "Hotel",
-        _ => {
-            panic!("Unknown microservice")
-        }
-    };
-
-    result.to_string()
-}
-
 /// Receives the id of the new AlGlobo instance.
 #[derive(StructOpt)]
 struct Cli {
     /// The new worker id (Type u32).
     id: usize,
+    /// The cluster configuration file, a TOML or a JSON file.
+    #[structopt(long)]
+    config: Option<String>,
+    /// The address to listen on, instead of the one of the configuration.
+    #[structopt(long)]
+    address: Option<String>,
+    /// The path of the log minus the name of the microservice and the extension.
+    #[structopt(long)]
+    log: Option<String>,
 }
 
 /// Microservice main
@@ -33,28 +34,126 @@ fn main() {
     let args = Cli::from_args();
     let id = args.id;
 
-    let name = id_to_microservice_name(id);
+    let config = match Config::load_or_default(args.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("[{}] Invalid configuration: {}", id, e);
+            return;
+        }
+    };
+    let registry = config.registry();
+    let participant = registry.get(id).expect("Unknown microservice");
+    let name = participant.name.clone();
+    let address = args.address.unwrap_or_else(|| participant.address.clone());
+    let directory = config.directory();
+    let coordinators: Vec<SocketAddr> = directory
+        .ids()
+        .filter_map(|peer_id| directory.get(peer_id))
+        .map(|peer| peer.coordinator)
+        .collect();
+    let others: Vec<SocketAddr> = registry
+        .iter()
+        .filter(|other| other.id != id)
+        .filter_map(|other| other.address.to_socket_addrs().ok()?.next())
+        .collect();
+    let prepared_timeout = config.prepared_timeout();
+    let log_path = args.log.unwrap_or(config.paths.participant_log);
 
-    let mut log = HashMap::new();
+    let mut log = ParticipantLog::open(&format!("{}{}.log", log_path, name.to_lowercase()));
     let mut response;
 
-    let socket = UdpSocket::bind(id_to_microservice(id)).expect("Could not bind socket");
+    let socket = UdpSocket::bind(&address).expect("Could not bind socket");
+    socket
+        .set_read_timeout(Some(prepared_timeout.max(Duration::from_millis(10)) / 2))
+        .expect("Error setting read timeout");
+
+    // The time at which the decision of each prepared transaction is asked for, the ones
+    // accepted before a restart are asked for after the timeout too
+    let mut prepared: HashMap<i32, Instant> = log
+        .prepared()
+        .into_iter()
+        .map(|t| (t, Instant::now() + prepared_timeout))
+        .collect();
 
     println!("{} service is up", name);
 
     loop {
-        let mut buf = [0; 13];
-        let (size, from) = socket.recv_from(&mut buf).expect("Receive socket error");
+        let now = Instant::now();
+        for (t, deadline) in prepared.iter_mut() {
+            if *deadline <= now {
+                ask_decision(&socket, id, &name, *t, log.epoch(), &coordinators, &others);
+                *deadline = now + prepared_timeout;
+            }
+        }
+
+        let mut buf = [0; MAX_FRAME_SIZE];
+        let (size, from) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            // The read timeout only gives the chance to ask for the decisions
+            Err(_) => continue,
+        };
 
         println!("[{}] received {} bytes", name, size);
 
-        let transaction = Transaction::deserialize(buf);
+        let transaction = match Transaction::try_from(&buf[..size]) {
+            Ok(transaction) => transaction,
+            Err(e) => {
+                println!("[{}] dropping malformed message from {}: {}", name, from, e);
+                continue;
+            }
+        };
+
+        // The answers to a query carry a decision already taken. Every alGlobo instance answers
+        // from its own log, so only the ones of the newest epoch seen or a newer one are trusted,
+        // the others may be deposed leaders or followers whit a stale replica. The other
+        // microservices answer whit their own state, so they are trusted whatever the epoch
+        if transaction.phase == TransactionState::Query
+            && transaction.transaction_state != TransactionState::Query
+        {
+            let trusted = others.contains(&from)
+                || (coordinators.contains(&from) && transaction.epoch >= log.epoch());
+            if trusted
+                && prepared.contains_key(&transaction.transaction_id)
+                && matches!(
+                    transaction.transaction_state,
+                    TransactionState::Commit | TransactionState::Abort
+                )
+            {
+                println!(
+                    "[{}] learnt {:?} for {} from {}",
+                    name, transaction.transaction_state, transaction.transaction_id, from
+                );
+                decide(&mut log, &name, &transaction, transaction.transaction_state);
+                prepared.remove(&transaction.transaction_id);
+            }
+            continue;
+        }
+
+        // Messages of leaders older than the newest one seen come from deposed leaders, queries
+        // only read the log so they are answered anyway
+        if transaction.epoch < log.epoch()
+            && transaction.transaction_state != TransactionState::Query
+        {
+            println!(
+                "[{}] rejecting {:?} for {} from a deposed leader, epoch {} is older than {}",
+                name,
+                transaction.transaction_state,
+                transaction.transaction_id,
+                transaction.epoch,
+                log.epoch()
+            );
+            continue;
+        }
+        if transaction.epoch > log.epoch() {
+            println!("[{}] new leader epoch {}", name, transaction.epoch);
+            log.set_epoch(transaction.epoch);
+        }
 
         match transaction.transaction_state {
             TransactionState::Prepare => {
                 println!(
-                    "[{}] received PREPARE for {}",
-                    name, transaction.transaction_id
+                    "[{}] received PREPARE for {} ({} reference {})",
+                    name, transaction.transaction_id, transaction.amount, transaction.reference
                 );
                 let state = match log.get(&transaction.transaction_id) {
                     Some(TransactionState::Accepted) | Some(TransactionState::Commit) => {
@@ -65,6 +164,10 @@ fn main() {
                         let is_success = rand::thread_rng().gen_bool(0.75);
                         if is_success {
                             log.insert(transaction.transaction_id, TransactionState::Accepted);
+                            prepared.insert(
+                                transaction.transaction_id,
+                                Instant::now() + prepared_timeout,
+                            );
                             TransactionState::Commit
                         } else {
                             log.insert(transaction.transaction_id, TransactionState::Abort);
@@ -78,9 +181,12 @@ fn main() {
 
                 response = Transaction {
                     transaction_id: transaction.transaction_id,
-                    amount: 0,
+                    amount: transaction.amount,
                     transaction_state: state,
                     service: id as i32,
+                    phase: transaction.transaction_state,
+                    reference: transaction.reference.clone(),
+                    epoch: transaction.epoch,
                 };
 
                 socket
@@ -92,13 +198,17 @@ fn main() {
                     "[{}] received COMMIT from {}",
                     name, transaction.transaction_id
                 );
-                log.insert(transaction.transaction_id, TransactionState::Commit);
+                let state = decide(&mut log, &name, &transaction, TransactionState::Commit);
+                prepared.remove(&transaction.transaction_id);
 
                 response = Transaction {
                     transaction_id: transaction.transaction_id,
-                    amount: 0,
-                    transaction_state: TransactionState::Commit,
+                    amount: transaction.amount,
+                    transaction_state: state,
                     service: id as i32,
+                    phase: transaction.transaction_state,
+                    reference: transaction.reference.clone(),
+                    epoch: transaction.epoch,
                 };
 
                 socket
@@ -110,22 +220,113 @@ fn main() {
                     "[{}] received ABORT from {}",
                     name, transaction.transaction_id
                 );
-                log.insert(transaction.transaction_id, TransactionState::Abort);
+                let state = decide(&mut log, &name, &transaction, TransactionState::Abort);
+                prepared.remove(&transaction.transaction_id);
 
                 response = Transaction {
                     transaction_id: transaction.transaction_id,
-                    amount: 0,
-                    transaction_state: TransactionState::Abort,
+                    amount: transaction.amount,
+                    transaction_state: state,
                     service: id as i32,
+                    phase: transaction.transaction_state,
+                    reference: transaction.reference.clone(),
+                    epoch: transaction.epoch,
                 };
 
                 socket
                     .send_to(&response.serialize(), from)
                     .expect("Error sending abort response");
             }
+            TransactionState::Query => {
+                // Prepare stands for a transaction accepted whit no decision yet, Wait for one
+                // that was never prepared. It's asked by a new leader and by the other
+                // microservices, which can not tell if this one takes part in the transaction,
+                // so the log is never changed
+                let state = match log.get(&transaction.transaction_id) {
+                    Some(TransactionState::Accepted) => TransactionState::Prepare,
+                    Some(TransactionState::Commit) => TransactionState::Commit,
+                    Some(TransactionState::Abort) => TransactionState::Abort,
+                    _ => TransactionState::Wait,
+                };
+                println!(
+                    "[{}] received QUERY for {}, answering {:?}",
+                    name, transaction.transaction_id, state
+                );
+
+                response = Transaction {
+                    transaction_id: transaction.transaction_id,
+                    amount: transaction.amount,
+                    transaction_state: state,
+                    service: id as i32,
+                    phase: transaction.transaction_state,
+                    reference: transaction.reference.clone(),
+                    epoch: transaction.epoch,
+                };
+
+                if let Err(e) = socket.send_to(&response.serialize(), from) {
+                    println!("[{}] error sending query response: {}", name, e);
+                }
+            }
             _ => {
                 println!("[{}] ??? {}", name, transaction.transaction_id);
             }
         }
     }
 }
+
+/// Logs the decision for the transaction and returns it. A transaction already decided keeps its
+/// decision, so a decision sent again, even by another leader, is never applied twice
+fn decide(
+    log: &mut ParticipantLog,
+    name: &str,
+    transaction: &Transaction,
+    decision: TransactionState,
+) -> TransactionState {
+    match log.get(&transaction.transaction_id) {
+        Some(state @ (TransactionState::Commit | TransactionState::Abort)) => {
+            println!(
+                "[{}] {} was already decided {:?}",
+                name, transaction.transaction_id, state
+            );
+            *state
+        }
+        _ => {
+            log.insert(transaction.transaction_id, decision);
+            decision
+        }
+    }
+}
+
+/// Asks every alGlobo instance and the other microservices for the decision of a transaction this
+/// microservice prepared, any of them that knows it answers whit it
+fn ask_decision(
+    socket: &UdpSocket,
+    id: usize,
+    name: &str,
+    t: i32,
+    epoch: u64,
+    coordinators: &[SocketAddr],
+    others: &[SocketAddr],
+) {
+    println!("[{}] asking for the decision of {}", name, t);
+    let query = Transaction {
+        transaction_id: t,
+        amount: Money::zero(Currency::default()),
+        transaction_state: TransactionState::Query,
+        service: id as i32,
+        phase: TransactionState::Query,
+        reference: String::new(),
+        epoch,
+    }
+    .serialize();
+    for coordinator in coordinators {
+        if let Err(e) = socket.send_to(&query, coordinator) {
+            println!("[{}] error asking {}: {}", name, coordinator, e);
+        }
+    }
+    for other in others {
+        if let Err(e) = socket.send_to(&query, other) {
+            println!("[{}] error asking {}: {}", name, other, e);
+        }
+    }
+}
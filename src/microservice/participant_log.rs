@@ -0,0 +1,117 @@
+use common::append_log;
+use common::transaction::TransactionState;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+
+/// The first field of the lines that hold an election epoch
+const EPOCH_ENTRY: &str = "epoch";
+
+/// Append only log where a microservice persists the state of every transaction before
+/// answering, so its promises outlive a restart. It also keeps the newest election epoch seen,
+/// so deposed leaders stay fenced after a restart
+pub struct ParticipantLog {
+    file: File,
+    states: HashMap<i32, TransactionState>,
+    epoch: u64,
+}
+
+impl ParticipantLog {
+    /// Opens the log at the given path, replaying the states it already contains. The torn
+    /// entry a crash may have left at the end is dropped, so it does not swallow the next one
+    pub fn open(path: &str) -> ParticipantLog {
+        let (file, content) = append_log::open(path).expect("Error opening participant log");
+        let mut log = ParticipantLog {
+            file,
+            states: HashMap::new(),
+            epoch: 0,
+        };
+
+        for line in content.lines() {
+            if let Some(epoch) = parse_epoch(line) {
+                log.epoch = log.epoch.max(epoch);
+                continue;
+            }
+            match parse_line(line) {
+                Some((t, state)) => {
+                    log.states.insert(t, state);
+                }
+                None => println!("[PARTICIPANT LOG] skipping corrupt entry {}", line),
+            }
+        }
+
+        log
+    }
+
+    /// Returns the last state logged for the transaction
+    pub fn get(&self, t: &i32) -> Option<&TransactionState> {
+        self.states.get(t)
+    }
+
+    /// Returns the transactions this microservice accepted whitout knowing their decision yet
+    pub fn prepared(&self) -> Vec<i32> {
+        self.states
+            .iter()
+            .filter(|(_, state)| **state == TransactionState::Accepted)
+            .map(|(t, _)| *t)
+            .collect()
+    }
+
+    /// Returns the newest election epoch seen
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Persists a newer election epoch and waits until it reaches the disk
+    pub fn set_epoch(&mut self, epoch: u64) {
+        self.file
+            .write_all(format!("{},{}\n", EPOCH_ENTRY, epoch).as_bytes())
+            .expect("Error writing participant log");
+        self.file
+            .sync_data()
+            .expect("Error syncing participant log");
+        self.epoch = epoch;
+    }
+
+    /// Persists the state of the transaction and waits until it reaches the disk
+    pub fn insert(&mut self, t: i32, state: TransactionState) {
+        let state_name = match state {
+            TransactionState::Accepted => "Accepted",
+            TransactionState::Commit => "Commit",
+            TransactionState::Abort => "Abort",
+            _ => {
+                panic!("Unrecognized participant state")
+            }
+        };
+
+        self.file
+            .write_all(format!("{},{}\n", t, state_name).as_bytes())
+            .expect("Error writing participant log");
+        self.file
+            .sync_data()
+            .expect("Error syncing participant log");
+        self.states.insert(t, state);
+    }
+}
+
+/// Parses a line of the log file that holds an epoch, returns None if it holds something else
+fn parse_epoch(line: &str) -> Option<u64> {
+    let mut fields = line.trim().split(',');
+    if fields.next()? != EPOCH_ENTRY {
+        return None;
+    }
+    fields.next()?.parse().ok()
+}
+
+/// Parses a line of the log file, returns None if the line is corrupt (e.g. a torn write)
+fn parse_line(line: &str) -> Option<(i32, TransactionState)> {
+    let mut fields = line.trim().split(',');
+    let t = fields.next()?.parse().ok()?;
+    let state = match fields.next()? {
+        "Accepted" => TransactionState::Accepted,
+        "Commit" => TransactionState::Commit,
+        "Abort" => TransactionState::Abort,
+        _ => return None,
+    };
+    Some((t, state))
+}